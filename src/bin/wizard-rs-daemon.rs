@@ -1,131 +1,1045 @@
-use interprocess::local_socket::LocalSocketListener;
-use socket2::{Domain, Protocol, Socket, Type};
+use chrono::{Local, NaiveDateTime};
+use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 
 use std::{
-    io::Read,
-    net::SocketAddr,
+    path::PathBuf,
     sync::{
         atomic::AtomicBool,
-        mpsc,
-        mpsc::{Receiver, Sender},
-        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
     },
-    thread,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use wizard_rs::daemon::{Msg, DAEMONNAME};
-use wizard_rs::program::Action;
-use wizard_rs::wizard::WIZARD_PORT;
+use wizard_rs::bulb::Bulb;
+use wizard_rs::capabilities::Capabilities;
+use wizard_rs::daemon::{
+    read_frame, write_frame, Answer, DaemonError, DaemonStatus, Msg, ProgramId, ProgramInfo,
+    Request, Response, ResumeFrom, StepInfo, DAEMONNAME,
+};
+use wizard_rs::pilot::Pilot;
+use wizard_rs::program::{
+    self, interpolate, Action, Compiled, EndBehavior, Issue, Op, Program, Severity,
+};
+use wizard_rs::push::{PushListener, StateChange};
+use wizard_rs::schedule::{Schedule, ScheduleId, ScheduleInfo, Task};
+use wizard_rs::script;
+use wizard_rs::solar::Location;
+use wizard_rs::wizard::Wizard;
+
+const USAGE: &str = "usage: wizard-rs-daemon [--fps N]";
+
+/// Frames per second sent during a fade unless `--fps` says otherwise.
+const DEFAULT_FPS: u32 = 10;
+/// Upper bound for `--fps`.
+const MAX_FPS: u32 = 30;
+
+/// How often the running programs' steps are written to the state file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far a program may fall behind its timing before it stops catching up.
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+/// How long a held program sleeps between checks for being stopped.
+const HOLD_CHECK: Duration = Duration::from_secs(60);
+
+/// How often schedules are checked for being due.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Control {
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// A program thread and the handles to steer it.
+struct Running {
+    program: Program,
+    compiled: Compiled,
+    info: Arc<Mutex<ProgramInfo>>,
+    control: Sender<Control>,
+    thread: JoinHandle<()>,
+}
+
+impl Running {
+    /// Tells the program to stop, without waiting for it. It may still be
+    /// retrying an unreachable bulb, so joining the returned thread is left
+    /// to whoever can afford to wait.
+    fn stop(self) -> JoinHandle<()> {
+        let _ = self.control.send(Control::Stop);
+        self.thread
+    }
+}
+
+/// Waits for stopped programs, so that they are done with their bulbs.
+fn join(stopped: Vec<JoinHandle<()>>) {
+    for thread in stopped {
+        let _ = thread.join();
+    }
+}
+
+/// A running program as kept in the state file, so it survives a restart.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    id: ProgramId,
+    program: Program,
+    bulbs: Vec<Bulb>,
+    step: usize,
+    paused: bool,
+    resume: ResumeFrom,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedSchedule {
+    id: ScheduleId,
+    schedule: Schedule,
+}
+
+/// Everything the daemon keeps across restarts.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    programs: Vec<Saved>,
+    schedules: Vec<SavedSchedule>,
+    #[serde(default)]
+    location: Option<Location>,
+}
+
+/// A schedule and when it next needs attention.
+struct Scheduled {
+    id: ScheduleId,
+    schedule: Schedule,
+    next: Option<NaiveDateTime>,
+    /// When the window it opened closes.
+    end: Option<NaiveDateTime>,
+}
 
-fn worker(rx: Receiver<Msg>) {
-    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-    let mut bulb_ip: Option<String> = None;
+impl Scheduled {
+    fn new(
+        id: ScheduleId,
+        schedule: Schedule,
+        now: NaiveDateTime,
+        location: Option<&Location>,
+    ) -> Scheduled {
+        // a window opened before the daemon restarted still closes on time
+        let end = schedule
+            .previous_start(now, location)
+            .and_then(|start| schedule.window_end(start, location))
+            .filter(|end| *end > now);
+        Scheduled {
+            id,
+            next: schedule.next_start(now, location),
+            end,
+            schedule,
+        }
+    }
 
-    let mut program: Vec<Action> = Vec::new();
-    let mut idx: usize = 0;
+    fn info(&self) -> ScheduleInfo {
+        ScheduleInfo {
+            id: self.id,
+            schedule: self.schedule.clone(),
+            next: self.next,
+            window_end: self.end,
+        }
+    }
+}
 
-    let mut run = true;
+/// Every program the daemon runs, each on its own thread and schedule.
+struct Programs {
+    wiz: Arc<Wizard>,
+    push: Option<PushListener>,
+    running: Vec<Running>,
+    /// Programs told to stop that may still be finishing a step, and the
+    /// bulbs they drove.
+    stopping: Vec<(Vec<Bulb>, JoinHandle<()>)>,
+    next_id: ProgramId,
+    schedules: Vec<Scheduled>,
+    next_schedule: ScheduleId,
+    location: Option<Location>,
+    /// Time between the frames of a fade.
+    frame: Duration,
+    state_path: PathBuf,
+    /// What was last written to `state_path`.
+    saved: Vec<u8>,
+}
 
-    while run {
-        if let Ok(msg) = rx.try_recv() {
-            match msg {
-                Msg::Stop => {
-                    program.clear();
-                    idx = 0;
-                    run = false;
+impl Programs {
+    /// Starts `program` on `bulbs`. A bulb follows one program at a time, so
+    /// programs already driving any of them are stopped.
+    fn start(&mut self, program: Program, bulbs: Vec<Bulb>) -> Result<ProgramId, DaemonError> {
+        let compiled = check(&program, &bulbs)?;
+        let replaced = self.release(&bulbs);
+
+        if let Some(push) = &self.push {
+            for bulb in bulbs.iter() {
+                if let Err(e) = push.register(bulb) {
+                    println!("{}: {}", bulb.mac, e);
                 }
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let saved = Saved {
+            id,
+            program,
+            bulbs,
+            step: 0,
+            paused: false,
+            resume: ResumeFrom::default(),
+        };
+        self.spawn(saved, compiled, replaced);
+        Ok(id)
+    }
 
-                Msg::Run(prog, ip) => {
-                    program = prog;
-                    bulb_ip = Some(ip);
-                    idx = 0;
+    /// Sets `bulbs` once, in the background, taking them from their programs.
+    fn apply(&mut self, pilot: Pilot, mut bulbs: Vec<Bulb>) {
+        let replaced = self.release(&bulbs);
+
+        let wiz = self.wiz.clone();
+        thread::spawn(move || {
+            join(replaced);
+            for bulb in bulbs.iter_mut() {
+                if let Err(e) = wiz.set_pilot_ack(bulb, &pilot) {
+                    println!("{}: {}", bulb.mac, e);
                 }
+            }
+        });
+    }
+
+    /// Stops the programs driving any of `bulbs`, returning their threads
+    /// to be joined outside the lock.
+    fn release(&mut self, bulbs: &[Bulb]) -> Vec<JoinHandle<()>> {
+        self.reap();
+
+        let (replaced, kept): (Vec<Running>, Vec<Running>) =
+            self.running.drain(..).partition(|running| {
+                let info = running.info.lock().unwrap();
+                info.bulbs
+                    .iter()
+                    .any(|b| bulbs.iter().any(|bulb| bulb.mac == b.mac))
+            });
+        self.running = kept;
+
+        let (stopping, kept): (Vec<_>, Vec<_>) =
+            self.stopping.drain(..).partition(|(driven, _)| {
+                driven
+                    .iter()
+                    .any(|b| bulbs.iter().any(|bulb| bulb.mac == b.mac))
+            });
+        self.stopping = kept;
 
-                _ => {
-                    println!("{:?}", msg);
+        let stopping = stopping.into_iter().map(|(_, thread)| thread);
+        replaced
+            .into_iter()
+            .map(Running::stop)
+            .chain(stopping)
+            .collect()
+    }
+
+    /// Restarts the programs and schedules left in the state file by the
+    /// last run.
+    fn restore(&mut self) {
+        let state: State = match std::fs::read(&self.state_path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(state) => state,
+                Err(e) => {
+                    println!("{}: {}", self.state_path.display(), e);
+                    return;
                 }
+            },
+            Err(_) => return,
+        };
+
+        self.location = state.location;
+        let now = Local::now().naive_local();
+        for saved in state.schedules {
+            self.next_schedule = self.next_schedule.max(saved.id + 1);
+            let scheduled = Scheduled::new(saved.id, saved.schedule, now, self.location.as_ref());
+            self.schedules.push(scheduled);
+        }
+
+        for mut saved in state.programs {
+            if let Some(push) = &self.push {
+                for bulb in saved.bulbs.iter() {
+                    if let Err(e) = push.register(bulb) {
+                        println!("{}: {}", bulb.mac, e);
+                    }
+                }
+            }
+            if saved.resume == ResumeFrom::Start {
+                saved.step = 0;
             }
+            self.next_id = self.next_id.max(saved.id + 1);
+            let compiled = match check(&saved.program, &saved.bulbs) {
+                Ok(compiled) => compiled,
+                Err(e) => {
+                    println!("not restoring program #{}: {}", saved.id, e);
+                    continue;
+                }
+            };
+            println!("restoring program #{} from step {}", saved.id, saved.step);
+            self.spawn(saved, compiled, Vec::new());
         }
+    }
+
+    /// Runs the program on its own thread once the `replaced` programs are
+    /// done with its bulbs.
+    fn spawn(&mut self, saved: Saved, compiled: Compiled, replaced: Vec<JoinHandle<()>>) {
+        let info = Arc::new(Mutex::new(ProgramInfo {
+            id: saved.id,
+            bulbs: saved.bulbs,
+            step: saved.step,
+            len: compiled.ops.len(),
+            paused: saved.paused,
+            error: None,
+            resume: saved.resume,
+        }));
+        let program = saved.program;
+        let (control, rx) = mpsc::channel();
+        let wiz = self.wiz.clone();
+        let changes = self.push.as_ref().map(|push| push.subscribe());
+        let tinfo = info.clone();
+        let tcompiled = compiled.clone();
+        let frame = self.frame;
+        let thread = thread::spawn(move || {
+            join(replaced);
+            run_program(&wiz, &tcompiled, &tinfo, &rx, changes, frame)
+        });
+
+        self.running.push(Running {
+            program,
+            compiled,
+            info,
+            control,
+            thread,
+        });
+    }
+
+    fn list(&mut self) -> Vec<ProgramInfo> {
+        self.reap();
+        self.running
+            .iter()
+            .map(|running| running.info.lock().unwrap().clone())
+            .collect()
+    }
+
+    fn current_step(&self, id: ProgramId) -> Result<StepInfo, DaemonError> {
+        let running = &self.running[self.position(id)?];
+        let info = running.info.lock().unwrap();
+        // steps only ever rest on ops that do something
+        let action = match running.compiled.ops.get(info.step) {
+            Some(Op::Do(action)) => Some(action.clone()),
+            Some(Op::Hold) => Some(Action::Hold),
+            _ => None,
+        };
+        Ok(StepInfo {
+            id,
+            step: info.step,
+            // empty programs end right away
+            action: action.ok_or(DaemonError::UnknownProgram(id))?,
+            paused: info.paused,
+        })
+    }
+
+    fn pause(&self, id: ProgramId) -> Result<(), DaemonError> {
+        self.send(id, Control::Pause)?;
+        // saved right away, before the program thread gets to it
+        self.running[self.position(id)?].info.lock().unwrap().paused = true;
+        Ok(())
+    }
+
+    fn resume(&self, id: ProgramId) -> Result<(), DaemonError> {
+        self.send(id, Control::Resume)?;
+        self.running[self.position(id)?].info.lock().unwrap().paused = false;
+        Ok(())
+    }
+
+    fn set_resume(&self, id: ProgramId, resume: ResumeFrom) -> Result<(), DaemonError> {
+        self.running[self.position(id)?].info.lock().unwrap().resume = resume;
+        Ok(())
+    }
+
+    fn stop(&mut self, id: ProgramId) -> Result<(), DaemonError> {
+        let idx = self.position(id)?;
+        let running = self.running.remove(idx);
+        let bulbs = running.info.lock().unwrap().bulbs.clone();
+        self.stopping.push((bulbs, running.stop()));
+        Ok(())
+    }
+
+    fn stop_all(&mut self) -> Vec<JoinHandle<()>> {
+        let stopping = self.stopping.drain(..).map(|(_, thread)| thread);
+        self.running
+            .drain(..)
+            .map(Running::stop)
+            .chain(stopping)
+            .collect()
+    }
+
+    fn send(&self, id: ProgramId, control: Control) -> Result<(), DaemonError> {
+        let idx = self.position(id)?;
+        // a program that just ended has dropped its receiver
+        self.running[idx]
+            .control
+            .send(control)
+            .map_err(|_| DaemonError::UnknownProgram(id))
+    }
+
+    fn position(&self, id: ProgramId) -> Result<usize, DaemonError> {
+        self.running
+            .iter()
+            .position(|running| running.info.lock().unwrap().id == id)
+            .ok_or(DaemonError::UnknownProgram(id))
+    }
 
-        if !program.is_empty() {
-            if idx >= program.len() {
-                idx = 0;
+    /// Forgets programs that ended on their own.
+    fn reap(&mut self) {
+        self.running.retain(|running| !running.thread.is_finished());
+        self.stopping.retain(|(_, thread)| !thread.is_finished());
+    }
+
+    fn add_schedule(&mut self, schedule: Schedule) -> Result<ScheduleId, DaemonError> {
+        let tasks = std::iter::once(&schedule.task).chain(schedule.window.iter().map(|w| &w.then));
+        for task in tasks {
+            if let Task::Program(program) = task {
+                check(program, &schedule.bulbs)?;
+            }
+        }
+
+        let id = self.next_schedule;
+        self.next_schedule += 1;
+        let now = Local::now().naive_local();
+        // only a start from now on opens a window
+        let mut scheduled = Scheduled::new(id, schedule, now, self.location.as_ref());
+        scheduled.end = None;
+        self.schedules.push(scheduled);
+        Ok(id)
+    }
+
+    /// Moves the schedules that follow the sun to the new location.
+    fn set_location(&mut self, location: Option<Location>) {
+        self.location = location;
+        let now = Local::now().naive_local();
+        for scheduled in self.schedules.iter_mut() {
+            scheduled.next = scheduled.schedule.next_start(now, location.as_ref());
+        }
+    }
+
+    fn preview(&self, schedule: &Schedule, count: usize) -> Vec<NaiveDateTime> {
+        let now = Local::now().naive_local();
+        schedule.upcoming(now, self.location.as_ref(), count)
+    }
+
+    fn list_schedules(&self) -> Vec<ScheduleInfo> {
+        self.schedules.iter().map(Scheduled::info).collect()
+    }
+
+    fn remove_schedule(&mut self, id: ScheduleId) -> Result<(), DaemonError> {
+        let idx = self
+            .schedules
+            .iter()
+            .position(|scheduled| scheduled.id == id)
+            .ok_or(DaemonError::UnknownSchedule(id))?;
+        self.schedules.remove(idx);
+        Ok(())
+    }
+
+    /// Runs what the schedules have due by `now`.
+    fn tick(&mut self, now: NaiveDateTime) {
+        let location = self.location.as_ref();
+        let mut due = Vec::new();
+        for scheduled in self.schedules.iter_mut() {
+            let schedule = &scheduled.schedule;
+            if scheduled.end.is_some_and(|end| now >= end) {
+                scheduled.end = None;
+                if let Some(window) = &schedule.window {
+                    due.push((scheduled.id, window.then.clone(), schedule.bulbs.clone()));
+                }
+            }
+            if let Some(start) = scheduled.next.filter(|next| now >= *next) {
+                due.push((scheduled.id, schedule.task.clone(), schedule.bulbs.clone()));
+                scheduled.end = schedule.window_end(start, location);
+                scheduled.next = None;
             }
+            if scheduled.next.is_none() {
+                scheduled.next = schedule.next_start(now, location);
+            }
+        }
 
-            let action = &program[idx];
+        for (id, task, bulbs) in due {
+            println!("schedule #{} is due", id);
+            match task {
+                Task::Program(program) => {
+                    if let Err(e) = self.start(program, bulbs) {
+                        println!("schedule #{}: {}", id, e);
+                    }
+                }
+                Task::Pilot(pilot) => self.apply(pilot, bulbs),
+            }
+        }
+    }
 
-            match action {
-                Action::Sleep(s) => {
-                    thread::sleep(std::time::Duration::from_secs(*s));
+    /// Writes the running programs and the schedules to the state file if
+    /// they changed since the last save.
+    fn save(&mut self) {
+        self.reap();
+        let programs: Vec<Saved> = self
+            .running
+            .iter()
+            .map(|running| {
+                let info = running.info.lock().unwrap();
+                Saved {
+                    id: info.id,
+                    program: running.program.clone(),
+                    bulbs: info.bulbs.clone(),
+                    step: info.step,
+                    paused: info.paused,
+                    resume: info.resume,
                 }
-                Action::SetPilot(pilot) => {
-                    let data = pilot.build();
-                    let addr: SocketAddr = format!("{}:{}", bulb_ip.clone().unwrap(), WIZARD_PORT)
-                        .parse()
-                        .unwrap();
+            })
+            .collect();
+        let schedules = self
+            .schedules
+            .iter()
+            .map(|scheduled| SavedSchedule {
+                id: scheduled.id,
+                schedule: scheduled.schedule.clone(),
+            })
+            .collect();
+
+        let data = serde_json::to_vec(&State {
+            programs,
+            schedules,
+            location: self.location,
+        })
+        .unwrap();
+        if data == self.saved {
+            return;
+        }
+        // a crash mid-write must not lose the previous state
+        let mut tmp = self.state_path.clone();
+        tmp.set_extension("json.tmp");
+        let result =
+            std::fs::write(&tmp, &data).and_then(|_| std::fs::rename(&tmp, &self.state_path));
+        match result {
+            Ok(()) => self.saved = data,
+            Err(e) => println!("{}: {}", self.state_path.display(), e),
+        }
+    }
+}
+
+/// Compiles `program`, refusing it when [`program::validate`] finds errors
+/// running it on `bulbs`. Warnings are only logged.
+fn check(program: &Program, bulbs: &[Bulb]) -> Result<Compiled, DaemonError> {
+    let compiled = program
+        .compile()
+        .map_err(|e| DaemonError::InvalidProgram(e.to_string()))?;
+    let caps: Vec<Capabilities> = bulbs.iter().map(|bulb| bulb.capabilities()).collect();
+    let (errors, warnings): (Vec<Issue>, Vec<Issue>) = program::validate(program, &caps)
+        .into_iter()
+        .partition(|issue| issue.severity == Severity::Error);
+    for warning in warnings {
+        println!("{}", warning);
+    }
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        return Err(DaemonError::InvalidProgram(errors.join("; ")));
+    }
+    Ok(compiled)
+}
+
+fn run_program(
+    wiz: &Wizard,
+    compiled: &Compiled,
+    info: &Mutex<ProgramInfo>,
+    control: &Receiver<Control>,
+    changes: Option<Receiver<StateChange>>,
+    frame: Duration,
+) {
+    let ops = &compiled.ops;
+    if ops.is_empty() {
+        return;
+    }
+    let (mut bulbs, mut idx, paused) = {
+        let info = info.lock().unwrap();
+        (info.bulbs.clone(), info.step % ops.len(), info.paused)
+    };
+    // a program restored while paused waits to be resumed
+    if paused && wait(control, info, Duration::ZERO, true).is_none() {
+        return;
+    }
+    let end = compiled.end;
+    let before: Vec<Option<Pilot>> = match end {
+        EndBehavior::Restore => bulbs
+            .iter()
+            .map(|bulb| wiz.get_pilot(bulb).ok().map(|state| state.to_pilot()))
+            .collect(),
+        EndBehavior::Loop | EndBehavior::Stop => Vec::new(),
+    };
+    let mut counters = vec![0; compiled.slots];
+    // a seeded program picks the same random actions on every run
+    let mut rng = match compiled.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    // what each bulb was last set to, where a fade starts from
+    let mut last: Vec<Option<Pilot>> = vec![None; bulbs.len()];
+    // when the current step is due; steps are timed from here rather than
+    // from when the previous one finished, so sends do not add up to drift
+    let mut deadline = Instant::now();
 
-                    let _ = socket.send_to(data.as_bytes(), &addr.into());
+    loop {
+        if idx >= ops.len() {
+            match end {
+                EndBehavior::Loop => idx = 0,
+                EndBehavior::Stop => return,
+                EndBehavior::Restore => {
+                    follow(&changes, &mut bulbs);
+                    for (bulb, pilot) in bulbs.iter_mut().zip(before.iter()) {
+                        if let Some(Err(e)) = pilot.as_ref().map(|p| wiz.set_pilot_ack(bulb, p)) {
+                            println!("{}: {}", bulb.mac, e);
+                        }
+                    }
+                    return;
                 }
             }
+        }
 
+        // loops that never do anything were refused by `check`
+        let picked = match ops[idx] {
+            Op::Do(ref action) => action.pick(&mut rng),
+            Op::Enter {
+                slot,
+                count,
+                end: past,
+            } => {
+                counters[slot] = 0;
+                idx = if count == 0 { past } else { idx + 1 };
+                continue;
+            }
+            Op::Again { slot, count, start } => {
+                counters[slot] += 1;
+                idx = if counters[slot] < count {
+                    start
+                } else {
+                    idx + 1
+                };
+                continue;
+            }
+            Op::Jump(target) => {
+                idx = target;
+                continue;
+            }
+            Op::Hold => {
+                info.lock().unwrap().step = idx;
+                while wait(control, info, HOLD_CHECK, false).is_some() {}
+                return;
+            }
+            Op::Stop => return,
+        };
+        info.lock().unwrap().step = idx;
+        // a random pick from an empty list does nothing
+        let Some(action) = picked else {
             idx += 1;
+            continue;
+        };
+
+        let duration = match &action {
+            Action::Sleep(duration) => *duration,
+            Action::SetPilot(pilot) => {
+                follow(&changes, &mut bulbs);
+                set_all(wiz, &mut bulbs, pilot, info);
+                last.fill(Some(pilot.clone()));
+                Duration::ZERO
+            }
+            Action::Fade {
+                to,
+                duration_ms,
+                easing,
+            } => {
+                follow(&changes, &mut bulbs);
+                let from: Vec<Pilot> = bulbs
+                    .iter()
+                    .zip(last.iter())
+                    .map(|(bulb, last)| match last {
+                        Some(pilot) => pilot.clone(),
+                        None => match wiz.get_pilot(bulb) {
+                            Ok(state) => state.to_pilot(),
+                            Err(_) => to.clone(),
+                        },
+                    })
+                    .collect();
+
+                let duration = Duration::from_millis(*duration_ms);
+                // timed from when the frames start: the deadline may lag
+                // behind after a slow step or fetching `from`, and the fade
+                // would skip its start to catch up
+                let mut start = Instant::now().max(deadline);
+                loop {
+                    let elapsed = start.elapsed();
+                    if elapsed >= duration {
+                        break;
+                    }
+                    let t = elapsed.as_secs_f32() / duration.as_secs_f32();
+                    for (bulb, from) in bulbs.iter().zip(from.iter()) {
+                        // a lost frame is made up for by the next one
+                        let _ = wiz.set_pilot(bulb.clone(), interpolate(from, to, t, *easing));
+                    }
+
+                    // frames keep to the clock however long sending took
+                    let frames = elapsed.as_nanos() / frame.as_nanos() + 1;
+                    let next = frame * frames as u32;
+                    match wait(control, info, next.min(duration) - elapsed, false) {
+                        Some(paused) => start += paused,
+                        None => return,
+                    }
+                }
+
+                set_all(wiz, &mut bulbs, to, info);
+                last.fill(Some(to.clone()));
+                // the next step is due once the fade as it ran is over
+                start.duration_since(deadline) + duration
+            }
+            // control flow is compiled into the other ops
+            _ => Duration::ZERO,
+        };
+
+        deadline += duration;
+        let now = Instant::now();
+        if now > deadline + MAX_LAG {
+            // after a bulb timed out, carry on from now instead of rushing
+            // through the steps that were missed
+            deadline = now;
         }
+        match wait(
+            control,
+            info,
+            deadline.saturating_duration_since(now),
+            false,
+        ) {
+            Some(paused) => deadline += paused,
+            None => return,
+        }
+
+        idx += 1;
     }
 }
 
+/// Keeps the bulbs' ips current with what they pushed.
+fn follow(changes: &Option<Receiver<StateChange>>, bulbs: &mut [Bulb]) {
+    if let Some(changes) = changes {
+        for change in changes.try_iter() {
+            if let Some(bulb) = bulbs.iter_mut().find(|b| b.mac == change.mac) {
+                bulb.ip = change.ip;
+            }
+        }
+    }
+}
+
+/// Sets every bulb to `pilot` and records in `info` whether they all took it.
+fn set_all(wiz: &Wizard, bulbs: &mut [Bulb], pilot: &Pilot, info: &Mutex<ProgramInfo>) {
+    let mut error = None;
+    for bulb in bulbs.iter_mut() {
+        // re-resolves the bulb by mac if its ip went stale
+        if let Err(e) = wiz.set_pilot_ack(bulb, pilot) {
+            println!("{}: {}", bulb.mac, e);
+            error = Some(DaemonError::Unreachable {
+                mac: bulb.mac.clone(),
+                message: e.to_string(),
+            });
+        }
+    }
+    let mut info = info.lock().unwrap();
+    info.bulbs = bulbs.to_vec();
+    info.error = error;
+}
+
+/// Waits `duration` while obeying pause and resume, and returns how long it
+/// was paused, or `None` once the program is stopped. Time spent paused does
+/// not count towards `duration`.
+fn wait(
+    control: &Receiver<Control>,
+    info: &Mutex<ProgramInfo>,
+    duration: Duration,
+    mut paused: bool,
+) -> Option<Duration> {
+    let mut remaining = duration;
+    let mut paused_for = Duration::ZERO;
+    loop {
+        let started = Instant::now();
+        let msg = match paused {
+            true => control.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => control.recv_timeout(remaining),
+        };
+        match paused {
+            true => paused_for += started.elapsed(),
+            false => remaining = remaining.saturating_sub(started.elapsed()),
+        }
+
+        match msg {
+            Ok(Control::Pause) => paused = true,
+            Ok(Control::Resume) => paused = false,
+            Ok(Control::Stop) | Err(RecvTimeoutError::Disconnected) => return None,
+            Err(RecvTimeoutError::Timeout) => return Some(paused_for),
+        }
+        info.lock().unwrap().paused = paused;
+    }
+}
+
+/// State shared by the threads serving client connections.
+struct Daemon {
+    programs: Mutex<Programs>,
+    run: Arc<AtomicBool>,
+    started: Instant,
+}
+
+impl Daemon {
+    /// Answers requests on `stream` until the client hangs up.
+    fn serve(&self, mut stream: LocalSocketStream) {
+        loop {
+            let (response, close) = match read_frame(&mut stream) {
+                Ok(Some(data)) => (self.handle(Request::decode(&data)), false),
+                Ok(None) => return,
+                // an oversized frame cannot be skipped, so the connection ends
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    (Response::Error(DaemonError::Malformed(e.to_string())), true)
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let data = serde_json::to_vec(&Answer::new(response)).unwrap();
+            if let Err(e) = write_frame(&mut stream, &data) {
+                println!("Error: {}", e);
+                return;
+            }
+            if close || !self.run.load(std::sync::atomic::Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+
+    fn handle(&self, msg: Result<Msg, DaemonError>) -> Response {
+        let done = |result: Result<(), DaemonError>| match result {
+            Ok(()) => Response::Done,
+            Err(e) => Response::Error(e),
+        };
+
+        // queries leave the state file alone; steps are saved periodically
+        let changes = matches!(
+            msg,
+            Ok(Msg::Run(..)
+                | Msg::RunGroup(..)
+                | Msg::Pause(_)
+                | Msg::Resume(_)
+                | Msg::StopProgram(_)
+                | Msg::SetResume(..)
+                | Msg::AddSchedule(_)
+                | Msg::RemoveSchedule(_)
+                | Msg::SetLocation(_)
+                | Msg::Import(..))
+        );
+
+        let mut programs = self.programs.lock().unwrap();
+        let response = match msg {
+            Err(e) => Response::Error(e),
+            Ok(Msg::Ping) => Response::Pong,
+            Ok(Msg::Status) => Response::Status(DaemonStatus {
+                version: String::from(env!("CARGO_PKG_VERSION")),
+                uptime_secs: self.started.elapsed().as_secs(),
+                programs: programs.list().len(),
+                push: programs.push.is_some(),
+                location: programs.location,
+            }),
+            Ok(Msg::Stop) => {
+                self.run.store(false, std::sync::atomic::Ordering::SeqCst);
+                Response::Done
+            }
+            Ok(Msg::Run(program, bulb)) => match programs.start(program, vec![bulb]) {
+                Ok(id) => Response::Started(id),
+                Err(e) => Response::Error(e),
+            },
+            Ok(Msg::RunGroup(program, bulbs)) => match programs.start(program, bulbs) {
+                Ok(id) => Response::Started(id),
+                Err(e) => Response::Error(e),
+            },
+            Ok(Msg::ListPrograms) => Response::Programs(programs.list()),
+            Ok(Msg::CurrentStep(id)) => match programs.current_step(id) {
+                Ok(step) => Response::Step(step),
+                Err(e) => Response::Error(e),
+            },
+            Ok(Msg::Pause(id)) => done(programs.pause(id)),
+            Ok(Msg::Resume(id)) => done(programs.resume(id)),
+            Ok(Msg::StopProgram(id)) => done(programs.stop(id)),
+            Ok(Msg::SetResume(id, resume)) => done(programs.set_resume(id, resume)),
+            Ok(Msg::AddSchedule(schedule)) => match programs.add_schedule(schedule) {
+                Ok(id) => Response::Scheduled(id),
+                Err(e) => Response::Error(e),
+            },
+            Ok(Msg::ListSchedules) => Response::Schedules(programs.list_schedules()),
+            Ok(Msg::RemoveSchedule(id)) => done(programs.remove_schedule(id)),
+            Ok(Msg::SetLocation(location)) => {
+                programs.set_location(location);
+                Response::Done
+            }
+            Ok(Msg::Preview(schedule, count)) => {
+                Response::Preview(programs.preview(&schedule, count))
+            }
+            Ok(Msg::Import(text, bulbs)) => {
+                let program = script::parse(&text)
+                    .map_err(|e| DaemonError::InvalidProgram(e.to_string()))
+                    .and_then(|program| programs.start(program, bulbs));
+                match program {
+                    Ok(id) => Response::Started(id),
+                    Err(e) => Response::Error(e),
+                }
+            }
+            Ok(Msg::Export(id)) => match programs.position(id) {
+                Ok(idx) => Response::Script(script::print(&programs.running[idx].program)),
+                Err(e) => Response::Error(e),
+            },
+        };
+        if changes {
+            programs.save();
+        }
+        response
+    }
+}
+
+struct Args {
+    /// Frames per second sent during a fade.
+    fps: u32,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args { fps: DEFAULT_FPS };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = format!("invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--fps" => {
+                args.fps = value.parse().map_err(|_| invalid)?;
+                if !(1..=MAX_FPS).contains(&args.fps) {
+                    return Err(format!("--fps must be between 1 and {}", MAX_FPS));
+                }
+            }
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    Ok(args)
+}
+
 fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let run = Arc::new(AtomicBool::new(true));
-    let (tx, rx): (Sender<Msg>, Receiver<Msg>) = mpsc::channel();
 
-    let tx_clone = tx.clone();
     let r_clone = run.clone();
     ctrlc::set_handler(move || {
-        let _ = tx_clone.send(Msg::Stop);
         r_clone.store(false, std::sync::atomic::Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
 
+    // an ephemeral port, so the daemon can run next to the GUI
+    let wiz = match Wizard::bind(0) {
+        Ok(wiz) => Arc::new(wiz),
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    // keeps the bulbs' ips current; the GUI may already hold the port
+    let push = match PushListener::start() {
+        Ok(push) => Some(push),
+        Err(e) => {
+            println!("not listening for pushes: {}", e);
+            None
+        }
+    };
+
+    let mut state_path = std::env::current_exe().unwrap_or_default();
+    state_path.pop();
+    state_path.push("daemon.json");
+
+    let mut programs = Programs {
+        wiz: wiz.clone(),
+        push,
+        running: Vec::new(),
+        stopping: Vec::new(),
+        next_id: 1,
+        schedules: Vec::new(),
+        next_schedule: 1,
+        location: None,
+        frame: Duration::from_secs(1) / args.fps,
+        state_path,
+        saved: Vec::new(),
+    };
+    programs.restore();
+
+    let daemon = Arc::new(Daemon {
+        programs: Mutex::new(programs),
+        run: run.clone(),
+        started: Instant::now(),
+    });
+
     let listener = LocalSocketListener::bind(DAEMONNAME).unwrap();
     listener
         .set_nonblocking(true)
         .expect("could not set nonblocking");
 
-    let t = thread::spawn(move || {
-        worker(rx);
-    });
-
+    let mut last_save = Instant::now();
+    let mut last_tick = Instant::now();
     while run.load(std::sync::atomic::Ordering::SeqCst) {
+        if last_tick.elapsed() >= TICK_INTERVAL {
+            let now = Local::now().naive_local();
+            daemon.programs.lock().unwrap().tick(now);
+            last_tick = Instant::now();
+        }
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            daemon.programs.lock().unwrap().save();
+            last_save = Instant::now();
+        }
+
         match listener.accept() {
-            Ok(mut stream) => {
-                let mut buf = [0u8; 1024];
-                if let Ok(size) = stream.read(&mut buf) {
-                    let msg: Msg = serde_json::from_slice(&buf[..size]).unwrap_or(Msg::Ignore);
-
-                    match msg {
-                        Msg::Stop => {
-                            let _ = tx.send(msg);
-                            run.store(false, std::sync::atomic::Ordering::SeqCst);
-                        }
-                        Msg::Run(_, _) => {
-                            let _ = tx.send(msg);
-                        }
-                        Msg::Ignore => {
-                            // println!("could not parse action");
-                            // println!("{:?}", msg);
-                        }
-                    }
-                };
+            Ok(stream) => {
+                // clients keep their connection open, so each gets a thread
+                let daemon = daemon.clone();
+                thread::spawn(move || daemon.serve(stream));
             }
             Err(e) => {
                 if e.kind() != std::io::ErrorKind::WouldBlock {
                     println!("Error: {}", e);
                 }
+                thread::sleep(Duration::from_millis(10));
             }
         }
     }
 
     let _ = std::fs::remove_file(DAEMONNAME);
 
-    println!("waiting for worker thread to finish");
-    t.join().unwrap();
+    println!("waiting for programs to finish");
+    let mut programs = daemon.programs.lock().unwrap();
+    // kept in the state file, to be restored on the next start
+    programs.save();
+    let stopped = programs.stop_all();
+    drop(programs);
+    join(stopped);
+    wiz.cleanup();
 }
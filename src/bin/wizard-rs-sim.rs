@@ -0,0 +1,90 @@
+use std::net::Ipv4Addr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use wizard_rs::sim::{SimBulb, SimConfig, Simulator};
+
+const USAGE: &str = "usage: wizard-rs-sim [--count N] [--first IP] [--module NAME] [--loss P] [--latency MS] [--seed N]";
+
+struct Args {
+    count: u32,
+    first: Ipv4Addr,
+    module_name: Option<String>,
+    config: SimConfig,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        count: 1,
+        first: Ipv4Addr::new(127, 0, 0, 2),
+        module_name: None,
+        config: SimConfig::default(),
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let invalid = format!("invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--count" => args.count = value.parse().map_err(|_| invalid)?,
+            "--first" => args.first = value.parse().map_err(|_| invalid)?,
+            "--module" => args.module_name = Some(value),
+            "--loss" => {
+                let loss: f64 = value.parse().map_err(|_| invalid)?;
+                if !(0.0..=1.0).contains(&loss) {
+                    return Err(format!("--loss must be between 0 and 1, got {}", loss));
+                }
+                args.config.loss = loss;
+            }
+            "--latency" => {
+                args.config.latency = Duration::from_millis(value.parse().map_err(|_| invalid)?)
+            }
+            "--seed" => args.config.seed = Some(value.parse().map_err(|_| invalid)?),
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    Ok(args)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let first = u32::from(args.first);
+    let bulbs = (0..args.count)
+        .map(|i| {
+            let mut bulb = SimBulb::new(Ipv4Addr::from(first + i));
+            if let Some(module_name) = &args.module_name {
+                bulb.module_name = module_name.clone();
+            }
+            bulb
+        })
+        .collect();
+
+    let sim = match Simulator::start(bulbs, args.config) {
+        Ok(sim) => sim,
+        Err(e) => {
+            println!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for bulb in sim.bulbs() {
+        println!("{} {} {}", bulb.ip, bulb.mac, bulb.module_name);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    })
+    .expect("Error setting Ctrl-C handler");
+    let _ = rx.recv();
+
+    sim.stop();
+}
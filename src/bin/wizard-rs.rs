@@ -1,49 +1,213 @@
 #![windows_subsystem = "windows"]
 use strum::IntoEnumIterator;
 
-use eframe::egui::{self, ComboBox, DragValue, Slider};
+use chrono::{NaiveDateTime, NaiveTime, Weekday};
+use eframe::egui::{self, DragValue, Slider};
 use wizard_rs::bulb::Bulb;
-use wizard_rs::pilot::{Method, Pilot};
-use wizard_rs::program::Action;
+use wizard_rs::daemon::{DaemonStatus, ProgramInfo, ResumeFrom};
+use wizard_rs::error::WizardError;
+use wizard_rs::pilot::{Method, Pilot, PilotState, DEFAULT_RGB, TEMP_MAX, TEMP_MIN};
+use wizard_rs::program::{
+    self, Action, Colors, Easing, EndBehavior, Issue, Program, Severity, Timeline,
+};
+use wizard_rs::push::{PushListener, StateChange};
 use wizard_rs::scenes::Scene;
+use wizard_rs::schedule::{Schedule, ScheduleInfo, Task, Trigger, Window};
+use wizard_rs::script;
+use wizard_rs::solar::{Location, SunEvent};
 use wizard_rs::wizard::Wizard;
 
 use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
 fn main() -> Result<(), eframe::Error> {
     // create eframe window
-    let mut options = eframe::NativeOptions::default();
-    options.persist_window = true;
+    let options = eframe::NativeOptions {
+        persist_window: true,
+        ..Default::default()
+    };
 
-    eframe::run_native("WiZard", options, Box::new(|_cc| Box::<App>::default()))
+    match Wizard::new() {
+        Ok(wiz) => eframe::run_native("WiZard", options, Box::new(|_cc| Box::new(App::new(wiz)))),
+        Err(e) => {
+            let message = e.to_string();
+            eframe::run_native(
+                "WiZard",
+                options,
+                Box::new(|_cc| Box::new(ErrorApp(message))),
+            )
+        }
+    }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Config {
-    bulbs: Vec<Bulb>,
-    selected: Option<usize>,
-    program: Vec<Action>,
+/// Shown instead of the main window when the Wizard socket cannot be set up.
+struct ErrorApp(String);
+
+impl eframe::App for ErrorApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.colored_label(egui::Color32::RED, &self.0);
+        });
+    }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            bulbs: Vec::new(),
-            selected: None,
-            program: Vec::new(),
-        }
+const DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// How much of a looping program the timeline shows.
+const TIMELINE_LIMIT: Duration = Duration::from_secs(10 * 60);
+/// Fades on the timeline step like the daemon's default frame rate.
+const TIMELINE_FRAME: Duration = Duration::from_millis(100);
+
+/// The schedule being put together in the Daemon window.
+#[derive(Default)]
+struct ScheduleForm {
+    name: String,
+    hour: u32,
+    minute: u32,
+    /// Starts relative to the sun instead of at `hour:minute`.
+    sun: Option<SunEvent>,
+    offset_mins: i32,
+    days: [bool; 7],
+    /// Turns the bulb off at `end_hour:end_minute`.
+    window: bool,
+    end_hour: u32,
+    end_minute: u32,
+    latitude: f64,
+    longitude: f64,
+    /// As last previewed by the daemon.
+    preview: Vec<NaiveDateTime>,
+}
+
+impl ScheduleForm {
+    fn schedule(&self, program: Program, bulbs: Vec<Bulb>) -> Option<Schedule> {
+        let at = match self.sun {
+            Some(event) => Trigger::Sun {
+                event,
+                offset_mins: self.offset_mins,
+            },
+            None => Trigger::At(NaiveTime::from_hms_opt(self.hour, self.minute, 0)?),
+        };
+        let window = match self.window {
+            true => {
+                let mut off = Pilot::new(Method::SetPilot);
+                off.set_state(false);
+                Some(Window {
+                    until: Trigger::At(NaiveTime::from_hms_opt(self.end_hour, self.end_minute, 0)?),
+                    then: Task::Pilot(off),
+                })
+            }
+            false => None,
+        };
+        Some(Schedule {
+            name: self.name.clone(),
+            days: DAYS
+                .iter()
+                .zip(self.days)
+                .filter(|(_, on)| *on)
+                .map(|(day, _)| *day)
+                .collect(),
+            at,
+            bulbs,
+            task: Task::Program(program),
+            window,
+        })
     }
 }
 
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Config {
+    bulbs: Vec<Bulb>,
+    selected: Option<usize>,
+    program: Program,
+}
+
 struct App {
     wiz: Wizard,
     bulbs: Vec<Bulb>,
     selected: Option<usize>,
     pilot: Pilot,
+    error: Option<WizardError>,
     config_path: std::path::PathBuf,
-    program: Vec<Action>,
+    program: Program,
+    /// As last listed by the daemon.
+    programs: Vec<ProgramInfo>,
+    /// As last reported by the daemon.
+    daemon_status: Option<DaemonStatus>,
+    /// As last listed by the daemon.
+    schedules: Vec<ScheduleInfo>,
+    schedule_form: ScheduleForm,
+    /// Where programs are imported from and exported to, in the
+    /// [`script`] format.
+    program_file: String,
+    /// Why the last import or export failed.
+    file_error: Option<String>,
+    /// The program as last simulated.
+    timeline: Option<Timeline>,
+    /// Seconds into the timeline.
+    scrub: f32,
+    /// `None` when another tool already listens for pushes.
+    push: Option<PushListener>,
+    changes: Option<Receiver<StateChange>>,
+    /// The selected bulb's state, while it is being asked for.
+    fetching: Option<Receiver<(Bulb, Result<PilotState, WizardError>)>>,
 }
 
 impl App {
+    /// Takes the selected bulb's state once its fetch is done.
+    fn apply_fetched(&mut self) {
+        let Some(fetching) = &self.fetching else {
+            return;
+        };
+        let (bulb, state) = match fetching.try_recv() {
+            Ok(fetched) => fetched,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.fetching = None;
+                return;
+            }
+        };
+        self.fetching = None;
+
+        match state {
+            Ok(state) => self.pilot = state.to_pilot(),
+            Err(e) => self.error = Some(e),
+        }
+        if let Some(known) = self.bulbs.iter_mut().find(|b| b.mac == bulb.mac) {
+            known.merge(&bulb);
+        }
+        if let Some(push) = &self.push {
+            if let Err(e) = push.register(&bulb) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Applies state changes the bulbs pushed since the last frame.
+    fn apply_changes(&mut self) {
+        let Some(changes) = &self.changes else {
+            return;
+        };
+        for change in changes.try_iter() {
+            for (idx, bulb) in self.bulbs.iter_mut().enumerate() {
+                if bulb.mac != change.mac {
+                    continue;
+                }
+                bulb.ip = change.ip.clone();
+                if self.selected == Some(idx) {
+                    self.pilot = change.state.to_pilot();
+                }
+            }
+        }
+    }
+
     fn load_config(&mut self) {
         let file = std::fs::File::open(&self.config_path);
         if let Ok(file) = file {
@@ -77,21 +241,36 @@ impl App {
     }
 }
 
-impl Default for App {
-    fn default() -> Self {
+impl App {
+    fn new(wiz: Wizard) -> Self {
         // load bulbs from file
 
-        let mut config_path = std::env::current_exe().unwrap();
+        let mut config_path = std::env::current_exe().unwrap_or_default();
         config_path.pop();
         config_path.push("bulbs.json");
 
+        let push = PushListener::start().ok();
+        let changes = push.as_ref().map(|push| push.subscribe());
+
         let mut app = Self {
-            wiz: Wizard::new(),
+            wiz,
             bulbs: Vec::new(),
             selected: None,
             pilot: Pilot::default(),
-            config_path: config_path,
-            program: Vec::new(),
+            error: None,
+            config_path,
+            program: Program::default(),
+            programs: Vec::new(),
+            daemon_status: None,
+            schedules: Vec::new(),
+            schedule_form: ScheduleForm::default(),
+            program_file: String::from("program.wiz"),
+            file_error: None,
+            timeline: None,
+            scrub: 0.0,
+            push,
+            changes,
+            fetching: None,
         };
 
         app.load_config();
@@ -101,6 +280,16 @@ impl Default for App {
 }
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.push.is_some() {
+            self.apply_changes();
+            // pushes arrive without any input, keep polling for them
+            ctx.request_repaint_after(Duration::from_millis(500));
+        }
+        self.apply_fetched();
+        if self.fetching.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.menu_button("File", |ui| {
                 if ui.button("Save").clicked() {
@@ -109,7 +298,17 @@ impl eframe::App for App {
                     }
                     ui.close_menu();
                 }
-            })
+            });
+
+            if let Some(error) = &self.error {
+                let message = error.to_string();
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, message);
+                    if ui.button("dismiss").clicked() {
+                        self.error = None;
+                    }
+                });
+            }
         });
 
         egui::Window::new("Bulbs").show(ctx, |ui| {
@@ -130,6 +329,7 @@ impl eframe::App for App {
                     }
                     if ui.button("select").clicked() {
                         self.selected = Some(idx);
+                        self.fetching = Some(self.wiz.fetch_pilot(bulb.clone()));
                     }
 
                     if ui.button("x").clicked() {
@@ -154,7 +354,8 @@ impl eframe::App for App {
 
             ui.horizontal(|ui| {
                 if ui.button("Discover").clicked() {
-                    self.wiz.discover();
+                    // the list below is read from `wiz.bulbs` every frame
+                    self.error = self.wiz.discover().err();
                 }
 
                 let searching = self.wiz.searching.load(Ordering::SeqCst);
@@ -164,16 +365,21 @@ impl eframe::App for App {
             });
 
             if let Ok(bulbs) = self.wiz.bulbs.try_lock() {
-                for (idx, bulb) in bulbs.iter().enumerate() {
+                for bulb in bulbs.iter() {
                     ui.horizontal(|ui| {
                         ui.label(&bulb.mac);
                         ui.label(&bulb.ip);
+                        if let Some(module_name) = &bulb.module_name {
+                            ui.label(module_name);
+                        }
+                        if let Some(fw_version) = &bulb.fw_version {
+                            ui.label(format!("fw {}", fw_version));
+                        }
+                        if let Some(rssi) = bulb.rssi {
+                            ui.label(format!("{} dBm", rssi));
+                        }
                         if ui.button("add").clicked() {
-                            self.bulbs.push(Bulb::new(
-                                bulb.ip.clone(),
-                                bulb.mac.clone(),
-                                bulb.mac.clone(),
-                            ));
+                            self.bulbs.push(bulb.clone());
                         }
                     });
                 }
@@ -183,42 +389,85 @@ impl eframe::App for App {
         egui::Window::new("Control").show(ctx, |ui| {
             if let Some(idx) = self.selected {
                 let bulb = &self.bulbs[idx];
+                let caps = bulb.capabilities();
                 ui.label(format!("{} {}", &bulb.name, &bulb.ip));
 
-                ui.horizontal(|ui| {
-                    ui.label("Dimming");
-                    let brightness = ui.add(Slider::new(&mut self.pilot.brightness, 0.1..=1.0));
-                    if brightness.changed() {
-                        let mut pilot = Pilot::new(Method::SetPilot);
-                        pilot.set_brightness(self.pilot.brightness);
-                        self.wiz.set_pilot(bulb.clone(), pilot);
-                    }
-                });
+                if caps.supports_dimming {
+                    ui.horizontal(|ui| {
+                        ui.label("Dimming");
+                        let brightness = ui.add(Slider::new(&mut self.pilot.brightness, 0.1..=1.0));
+                        if brightness.changed() {
+                            let mut pilot = Pilot::new(Method::SetPilot);
+                            pilot.set_brightness(self.pilot.brightness);
+                            self.error = self.wiz.set_pilot(bulb.clone(), pilot).err();
+                        }
+                    });
+                }
 
-                ui.horizontal(|ui| {
-                    ui.label("Speed");
-                    let _speed = ui.add(Slider::new(&mut self.pilot.speed, 0.2..=2.0));
-                });
+                if caps.supports_effects {
+                    ui.horizontal(|ui| {
+                        ui.label("Speed");
+                        let _speed = ui.add(Slider::new(&mut self.pilot.speed, 0.2..=2.0));
+                    });
+                }
 
                 ui.horizontal(|ui| {
                     if ui.button("on").clicked() {
                         let mut pilot = Pilot::new(Method::SetPilot);
                         pilot.set_state(true);
                         pilot.set_brightness(self.pilot.brightness);
-                        self.wiz.set_pilot(bulb.clone(), pilot);
+                        self.error = self.wiz.set_pilot(bulb.clone(), pilot).err();
                     }
                     if ui.button("off").clicked() {
                         let mut pilot = Pilot::new(Method::SetPilot);
                         pilot.set_state(false);
-                        self.wiz.set_pilot(bulb.clone(), pilot);
+                        self.error = self.wiz.set_pilot(bulb.clone(), pilot).err();
                     }
                 });
 
-                let mut rgb = self.pilot.rgb.unwrap_or([0.0, 0.0, 255.0]);
-                let color_selector = ui.color_edit_button_rgb(&mut rgb);
-                if color_selector.changed() {
-                    self.pilot.rgb = Some(rgb);
-                    self.wiz.set_pilot(bulb.clone(), self.pilot.clone());
+                if caps.supports_rgb {
+                    let mut rgb = self.pilot.rgb.unwrap_or(DEFAULT_RGB);
+                    let color_selector = ui.color_edit_button_rgb(&mut rgb);
+                    if color_selector.changed() {
+                        self.pilot.rgb = Some(rgb);
+                        self.pilot.temp = None;
+                        self.pilot.scene = None;
+                        self.error = self.wiz.set_pilot(bulb.clone(), self.pilot.clone()).err();
+                    }
+                }
+
+                if let Some(range) = caps.supports_temp.clone() {
+                    ui.horizontal(|ui| {
+                        ui.label("Kelvin");
+                        let mut temp = self.pilot.temp.unwrap_or(*range.end());
+                        let kelvin = ui.add(Slider::new(&mut temp, range));
+                        if kelvin.changed() {
+                            self.pilot.temp = Some(temp);
+                            self.pilot.rgb = None;
+                            self.pilot.scene = None;
+                            self.pilot.cold = None;
+                            self.pilot.warm = None;
+                            self.error = self.wiz.set_pilot(bulb.clone(), self.pilot.clone()).err();
+                        }
+                    });
+                }
+
+                if caps.supports_rgb {
+                    ui.horizontal(|ui| {
+                        let mut cold = self.pilot.cold.unwrap_or(0);
+                        let mut warm = self.pilot.warm.unwrap_or(0);
+                        ui.label("Cold");
+                        let cold_changed = ui.add(DragValue::new(&mut cold)).changed();
+                        ui.label("Warm");
+                        let warm_changed = ui.add(DragValue::new(&mut warm)).changed();
+                        if cold_changed || warm_changed {
+                            self.pilot.cold = Some(cold);
+                            self.pilot.warm = Some(warm);
+                            self.pilot.temp = None;
+                            self.pilot.scene = None;
+                            self.error = self.wiz.set_pilot(bulb.clone(), self.pilot.clone()).err();
+                        }
+                    });
                 }
             }
         });
@@ -226,15 +475,16 @@ impl eframe::App for App {
         egui::Window::new("Scenes").vscroll(true).show(ctx, |ui| {
             if let Some(idx) = self.selected {
                 let bulb = &self.bulbs[idx];
+                let caps = bulb.capabilities();
                 ui.label(format!("{} {}", &bulb.name, &bulb.ip));
 
-                for scene in Scene::iter() {
+                for scene in Scene::iter().filter(|scene| caps.supports_scene(*scene)) {
                     if ui.button(scene.to_string()).clicked() {
                         let mut pilot = Pilot::new(Method::SetPilot);
                         pilot.set_scene(scene);
                         pilot.set_brightness(self.pilot.brightness);
                         pilot.set_speed(self.pilot.speed);
-                        self.wiz.set_pilot(bulb.clone(), pilot);
+                        self.error = self.wiz.set_pilot(bulb.clone(), pilot).err();
                     }
                 }
             }
@@ -242,127 +492,753 @@ impl eframe::App for App {
 
         egui::Window::new("Daemon").vscroll(true).show(ctx, |ui| {
             let daemon = self.wiz.daemon.clone();
+            let connected = daemon.lock().unwrap().is_some();
 
-            ui.label(format!(
-                "Daemon: {}",
-                match daemon.lock().unwrap().is_some() {
-                    true => "connected",
-                    false => "not connected",
-                }
-            ));
+            ui.label(match (&self.daemon_status, connected) {
+                (_, false) => String::from("Daemon: not connected"),
+                (None, true) => String::from("Daemon: connected"),
+                (Some(status), true) => format!(
+                    "Daemon: connected, v{}, up {}s, {} programs",
+                    status.version, status.uptime_secs, status.programs
+                ),
+            });
 
             ui.horizontal(|ui| {
                 if ui.button("connect").clicked() {
-                    self.wiz.daemon_connect();
+                    // only an answer proves the daemon is there
+                    match self
+                        .wiz
+                        .daemon_connect()
+                        .and_then(|_| self.wiz.daemon_status())
+                    {
+                        Ok(status) => self.daemon_status = Some(status),
+                        Err(e) => self.error = Some(e),
+                    }
                 };
 
+                if ui.button("status").clicked() {
+                    match self.wiz.daemon_status() {
+                        Ok(status) => self.daemon_status = Some(status),
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+
                 if ui.button("shutdown").clicked() {
-                    self.wiz.daemon_shutdown();
+                    self.error = self.wiz.daemon_shutdown().err();
+                    self.daemon_status = None;
                 }
             });
 
             ui.separator();
 
-            if ui.button("run").clicked() {
-                if !self.program.is_empty() {
-                    let bulb_ip = self.bulbs[self.selected.unwrap()].ip.clone();
-                    self.wiz.daemon_run_program(self.program.clone(), bulb_ip);
+            ui.horizontal(|ui| {
+                if ui.button("run").clicked() && !self.program.actions.is_empty() {
+                    if let Some(idx) = self.selected {
+                        let bulb = self.bulbs[idx].clone();
+                        self.error = self
+                            .wiz
+                            .daemon_run_program(self.program.clone(), bulb)
+                            .err();
+                    }
                 }
-            }
+
+                if ui.button("run on all").clicked() && !self.program.actions.is_empty() {
+                    self.error = self
+                        .wiz
+                        .daemon_run_group(self.program.clone(), self.bulbs.clone())
+                        .err();
+                }
+            });
 
             ui.separator();
 
-            let mut to_delete: Option<usize> = None;
-            let mut to_swap: Option<(usize, usize)> = None;
-            let program_len = self.program.len();
-            for (idx, action) in self.program.iter_mut().enumerate() {
-                match action {
-                    Action::Sleep(s) => {
-                        ui.horizontal(|ui| {
-                            ui.label(format!("sleep: "));
-
-                            ui.add(DragValue::new(s).speed(0.1).clamp_range(0.0..=10.0));
-
-                            if ui.button("remove").clicked() {
-                                to_delete = Some(idx);
-                            }
-                        });
+            ui.horizontal(|ui| {
+                ui.label("programs");
+                if ui.button("refresh").clicked() {
+                    match self.wiz.daemon_list_programs() {
+                        Ok(programs) => self.programs = programs,
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            });
 
-                        ui.horizontal(|ui| {
-                            if ui.button("up").clicked() {
-                                if idx > 0 {
-                                    to_swap = Some((idx, idx - 1));
-                                }
-                            }
+            for program in self.programs.iter_mut() {
+                ui.horizontal(|ui| {
+                    let names: Vec<&str> = program.bulbs.iter().map(|b| b.name.as_str()).collect();
+                    ui.label(format!(
+                        "#{} {} step {}/{}",
+                        program.id,
+                        names.join(", "),
+                        program.step + 1,
+                        program.len
+                    ));
 
-                            if ui.button("down").clicked() {
-                                if idx < program_len - 1 {
-                                    to_swap = Some((idx, idx + 1));
-                                }
+                    if program.paused {
+                        if ui.button("resume").clicked() {
+                            self.error = self.wiz.daemon_resume(program.id).err();
+                            program.paused = false;
+                        }
+                    } else if ui.button("pause").clicked() {
+                        self.error = self.wiz.daemon_pause(program.id).err();
+                        program.paused = true;
+                    }
+
+                    if ui.button("stop").clicked() {
+                        self.error = self.wiz.daemon_stop_program(program.id).err();
+                    }
+
+                    if ui.button("export").clicked() {
+                        match self.wiz.daemon_export(program.id) {
+                            Ok(text) => {
+                                self.file_error = std::fs::write(&self.program_file, text)
+                                    .err()
+                                    .map(|e| e.to_string())
                             }
-                        });
+                            Err(e) => self.error = Some(e),
+                        }
+                    }
+
+                    // where the program picks up after the daemon restarts
+                    let mut from_start = program.resume == ResumeFrom::Start;
+                    if ui.checkbox(&mut from_start, "restart from start").changed() {
+                        let resume = match from_start {
+                            true => ResumeFrom::Start,
+                            false => ResumeFrom::LastStep,
+                        };
+                        self.error = self.wiz.daemon_set_resume(program.id, resume).err();
+                        program.resume = resume;
+                    }
+                });
+                if let Some(error) = &program.error {
+                    ui.colored_label(egui::Color32::RED, error.to_string());
+                }
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("schedules");
+                if ui.button("refresh").clicked() {
+                    match self.wiz.daemon_list_schedules() {
+                        Ok(schedules) => self.schedules = schedules,
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            });
+
+            let mut removed = None;
+            for info in self.schedules.iter() {
+                ui.horizontal(|ui| {
+                    let next = match info.next {
+                        Some(next) => next.format("%a %d %b %H:%M").to_string(),
+                        None => String::from("never"),
+                    };
+                    ui.label(format!("#{} {} next {}", info.id, info.schedule.name, next));
+                    if ui.button("remove").clicked() {
+                        self.error = self.wiz.daemon_remove_schedule(info.id).err();
+                        removed = Some(info.id);
+                    }
+                });
+            }
+            if let Some(id) = removed {
+                self.schedules.retain(|info| info.id != id);
+            }
+
+            let form = &mut self.schedule_form;
+            ui.horizontal(|ui| {
+                ui.label("name");
+                ui.text_edit_singleline(&mut form.name);
+            });
+            ui.horizontal(|ui| {
+                ui.label("location");
+                ui.add(
+                    DragValue::new(&mut form.latitude)
+                        .speed(0.1)
+                        .clamp_range(-90.0..=90.0),
+                );
+                ui.add(
+                    DragValue::new(&mut form.longitude)
+                        .speed(0.1)
+                        .clamp_range(-180.0..=180.0),
+                );
+                if ui.button("set").clicked() {
+                    let location = Location {
+                        latitude: form.latitude,
+                        longitude: form.longitude,
+                    };
+                    self.error = self.wiz.daemon_set_location(Some(location)).err();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("at");
+                egui::ComboBox::from_id_source("schedule at")
+                    .selected_text(match form.sun {
+                        Some(event) => format!("{:?}", event),
+                        None => String::from("time"),
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut form.sun, None, "time");
+                        for event in [SunEvent::Sunrise, SunEvent::Sunset, SunEvent::CivilDusk] {
+                            ui.selectable_value(&mut form.sun, Some(event), format!("{:?}", event));
+                        }
+                    });
+                match form.sun {
+                    Some(_) => {
+                        ui.add(
+                            DragValue::new(&mut form.offset_mins)
+                                .clamp_range(-720..=720)
+                                .suffix(" min"),
+                        );
+                    }
+                    None => {
+                        ui.add(DragValue::new(&mut form.hour).clamp_range(0..=23));
+                        ui.add(DragValue::new(&mut form.minute).clamp_range(0..=59));
+                    }
+                }
+                for (day, on) in DAYS.iter().zip(form.days.iter_mut()) {
+                    ui.checkbox(on, day.to_string());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut form.window, "turn off at");
+                ui.add(DragValue::new(&mut form.end_hour).clamp_range(0..=23));
+                ui.add(DragValue::new(&mut form.end_minute).clamp_range(0..=59));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("schedule program").clicked() && !self.program.actions.is_empty() {
+                    let bulb = self.selected.map(|idx| self.bulbs[idx].clone());
+                    let schedule =
+                        bulb.and_then(|bulb| form.schedule(self.program.clone(), vec![bulb]));
+                    if let Some(schedule) = schedule {
+                        self.error = self.wiz.daemon_add_schedule(schedule).err();
+                    }
+                }
+
+                if ui.button("preview").clicked() {
+                    if let Some(schedule) = form.schedule(Program::default(), Vec::new()) {
+                        match self.wiz.daemon_preview(schedule, 5) {
+                            Ok(preview) => form.preview = preview,
+                            Err(e) => self.error = Some(e),
+                        }
+                    }
+                }
+            });
+            for start in form.preview.iter() {
+                ui.label(start.format("%a %d %b %H:%M").to_string());
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("program file");
+                ui.text_edit_singleline(&mut self.program_file);
+
+                if ui.button("import").clicked() {
+                    let program = std::fs::read_to_string(&self.program_file)
+                        .map_err(|e| e.to_string())
+                        .and_then(|text| script::parse(&text).map_err(|e| e.to_string()));
+                    match program {
+                        Ok(program) => {
+                            self.program = program;
+                            self.file_error = None;
+                        }
+                        Err(e) => self.file_error = Some(e),
+                    }
+                }
+
+                if ui.button("export").clicked() {
+                    self.file_error =
+                        std::fs::write(&self.program_file, script::print(&self.program))
+                            .err()
+                            .map(|e| e.to_string());
+                }
+
+                // the daemon parses the file, and reports where it is wrong
+                if ui.button("run file on all").clicked() {
+                    match std::fs::read_to_string(&self.program_file) {
+                        Ok(text) => {
+                            self.file_error = None;
+                            self.error = self.wiz.daemon_import(text, self.bulbs.clone()).err();
+                        }
+                        Err(e) => self.file_error = Some(e.to_string()),
+                    }
+                }
+            });
+            if let Some(error) = &self.file_error {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!("{}: {}", self.program_file, error),
+                );
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("at the end");
+                egui::ComboBox::from_id_source("end behavior")
+                    .selected_text(format!("{:?}", self.program.end))
+                    .show_ui(ui, |ui| {
+                        for end in [EndBehavior::Loop, EndBehavior::Stop, EndBehavior::Restore] {
+                            ui.selectable_value(&mut self.program.end, end, format!("{:?}", end));
+                        }
+                    });
+
+                let mut seeded = self.program.seed.is_some();
+                ui.checkbox(&mut seeded, "seed");
+                match (seeded, &mut self.program.seed) {
+                    (true, Some(seed)) => {
+                        ui.add(DragValue::new(seed));
                     }
-                    Action::SetPilot(p) => {
-                        ui.label(format!("set pilot"));
+                    (true, seed) => *seed = Some(0),
+                    (false, seed) => *seed = None,
+                }
+            });
+
+            // checked against the bulb it would run on, or all of them
+            let caps: Vec<_> = match self.selected {
+                Some(idx) => vec![self.bulbs[idx].capabilities()],
+                None => self.bulbs.iter().map(|bulb| bulb.capabilities()).collect(),
+            };
+            let issues = program::validate(&self.program, &caps);
+            show_issues(ui, &issues, &[]);
 
-                        ui.checkbox(&mut p.state, "state");
+            ui.horizontal(|ui| {
+                if ui.button("simulate").clicked() {
+                    self.timeline =
+                        program::simulate(&self.program, TIMELINE_LIMIT, TIMELINE_FRAME).ok();
+                }
+                if let Some(timeline) = &self.timeline {
+                    let total = timeline.duration.as_secs_f32();
+                    ui.label(match timeline.cut {
+                        true => format!("runs on, first {:.0}s shown", total),
+                        false => format!("runs {:.1}s", total),
+                    });
+                }
+            });
+            if let Some(timeline) = &self.timeline {
+                let total = timeline.duration.as_secs_f32();
+                self.scrub = self.scrub.min(total);
+                timeline_strip(ui, timeline, self.scrub);
+                ui.horizontal(|ui| {
+                    ui.add(Slider::new(&mut self.scrub, 0.0..=total).suffix(" s"));
+                    let state = timeline.at(Duration::from_secs_f32(self.scrub));
+                    ui.label(match state {
+                        Some(pilot) => describe(pilot),
+                        None => String::from("not set yet"),
+                    });
+                });
+            }
 
-                        ui.horizontal(|ui| {
-                            if ui.button("rgb").clicked() {
-                                if p.rgb.is_none() {
-                                    p.rgb = Some([0.0, 0.0, 255.0]);
-                                } else {
-                                    p.rgb = None;
+            actions_editor(ui, &mut self.program.actions, &issues, &[]);
+        });
+    }
+}
+
+/// About how `pilot` looks, for drawing timelines. Scenes, which change
+/// color on their own, are grey.
+fn light_color(pilot: &Pilot) -> egui::Color32 {
+    if !pilot.state {
+        return egui::Color32::BLACK;
+    }
+    let [r, g, b] = match (pilot.rgb, pilot.temp, pilot.scene) {
+        (Some(rgb), _, _) => rgb,
+        (_, Some(temp), _) => {
+            // from candle orange at the warm end to blueish white
+            let t =
+                (temp.clamp(TEMP_MIN, TEMP_MAX) - TEMP_MIN) as f32 / (TEMP_MAX - TEMP_MIN) as f32;
+            [1.0 - 0.2 * t, 0.6 + 0.3 * t, 0.25 + 0.75 * t]
+        }
+        (_, _, Some(_)) => [0.5, 0.5, 0.5],
+        _ => [1.0, 0.95, 0.85],
+    };
+    let channel = |c: f32| (c * pilot.brightness * 255.0).round() as u8;
+    egui::Color32::from_rgb(channel(r), channel(g), channel(b))
+}
+
+/// A short description of `pilot`, like `#ff0000 at 80%`.
+fn describe(pilot: &Pilot) -> String {
+    if !pilot.state {
+        return String::from("off");
+    }
+    let light = match (pilot.rgb, pilot.temp, pilot.scene) {
+        (Some(rgb), _, _) => {
+            let [r, g, b] = rgb.map(|c| (c * 255.0).round() as u8);
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        }
+        (_, Some(temp), _) => format!("{}K", temp),
+        (_, _, Some(scene)) => scene.to_string(),
+        _ => String::from("on"),
+    };
+    format!("{} at {}%", light, pilot.dimming())
+}
+
+/// Draws `timeline` as a strip of the colors the bulbs go through, with a
+/// line `scrub` seconds in.
+fn timeline_strip(ui: &mut egui::Ui, timeline: &Timeline, scrub: f32) {
+    let size = egui::vec2(ui.available_width(), 24.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::DARK_GRAY);
+
+    let total = timeline.duration.as_secs_f32().max(f32::EPSILON);
+    let x = |secs: f32| rect.left() + rect.width() * (secs / total).min(1.0);
+    for (idx, (at, pilot)) in timeline.points.iter().enumerate() {
+        let end = match timeline.points.get(idx + 1) {
+            Some((next, _)) => *next,
+            None => timeline.duration,
+        };
+        let span =
+            egui::Rect::from_x_y_ranges(x(at.as_secs_f32())..=x(end.as_secs_f32()), rect.y_range());
+        painter.rect_filled(span, 0.0, light_color(pilot));
+    }
+
+    let scrub = x(scrub);
+    painter.vline(
+        scrub,
+        rect.y_range(),
+        egui::Stroke::new(2.0, egui::Color32::WHITE),
+    );
+}
+
+/// Lists the issues with the action at `at`, or with the whole program.
+fn show_issues(ui: &mut egui::Ui, issues: &[Issue], at: &[usize]) {
+    for issue in issues.iter().filter(|issue| issue.at == at) {
+        let color = match issue.severity {
+            Severity::Error => egui::Color32::RED,
+            Severity::Warning => egui::Color32::YELLOW,
+        };
+        ui.colored_label(color, &issue.message);
+    }
+}
+
+/// Edits a program's actions, and the bodies of its blocks in turn.
+/// `issues` are shown next to the actions they are about; `at` is where
+/// `actions` are in the program.
+fn actions_editor(ui: &mut egui::Ui, actions: &mut Vec<Action>, issues: &[Issue], at: &[usize]) {
+    let mut to_delete: Option<usize> = None;
+    let mut to_swap: Option<(usize, usize)> = None;
+    let program_len = actions.len();
+    for (idx, action) in actions.iter_mut().enumerate() {
+        let kind = match action {
+            Action::Label(_) => "label",
+            Action::Goto(_) => "goto",
+            Action::Hold => "hold",
+            Action::Stop => "stop",
+            _ => "",
+        };
+        let at = [at, &[idx]].concat();
+        ui.push_id(idx, |ui| {
+            show_issues(ui, issues, &at);
+
+            match action {
+                Action::Sleep(duration) => {
+                    ui.horizontal(|ui| {
+                        ui.label("sleep: ");
+
+                        let mut ms = duration.as_millis() as u64;
+                        ui.add(
+                            DragValue::new(&mut ms)
+                                .speed(10.0)
+                                .clamp_range(0..=60_000)
+                                .suffix(" ms"),
+                        );
+                        *duration = Duration::from_millis(ms);
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                }
+                Action::SetPilot(p) => {
+                    ui.label("set pilot");
+
+                    pilot_editor(ui, p);
+                }
+                Action::Fade {
+                    to,
+                    duration_ms,
+                    easing,
+                } => {
+                    ui.horizontal(|ui| {
+                        ui.label("fade over");
+                        ui.add(
+                            DragValue::new(duration_ms)
+                                .speed(10.0)
+                                .clamp_range(0..=60_000)
+                                .suffix(" ms"),
+                        );
+
+                        egui::ComboBox::from_id_source("easing")
+                            .selected_text(format!("{:?}", easing))
+                            .show_ui(ui, |ui| {
+                                for choice in
+                                    [Easing::Linear, Easing::EaseInOut, Easing::Perceptual]
+                                {
+                                    ui.selectable_value(easing, choice, format!("{:?}", choice));
                                 }
-                            }
+                            });
 
-                            if let Some(rgb) = &mut p.rgb {
-                                ui.color_edit_button_rgb(rgb);
-                            }
-                        });
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
 
-                        ui.horizontal(|ui| {
-                            ui.label("brightness");
-                            ui.add(Slider::new(&mut p.brightness, 0.1..=1.0));
-                        });
+                    pilot_editor(ui, to);
+                }
+                Action::RandomColor { from, brightness } => {
+                    ui.horizontal(|ui| {
+                        ui.label("random color");
 
-                        ui.horizontal(|ui| {
-                            if ui.button("up").clicked() {
-                                if idx > 0 {
-                                    to_swap = Some((idx, idx - 1));
+                        let mut hues = matches!(from, Colors::Hues { .. });
+                        ui.radio_value(&mut hues, false, "palette");
+                        ui.radio_value(&mut hues, true, "hues");
+                        match (hues, &*from) {
+                            (true, Colors::Palette(_)) => {
+                                *from = Colors::Hues {
+                                    min: 0.0,
+                                    max: 360.0,
                                 }
                             }
+                            (false, Colors::Hues { .. }) => {
+                                *from = Colors::Palette(vec![[255, 0, 0], [0, 0, 255]])
+                            }
+                            _ => {}
+                        }
 
-                            if ui.button("down").clicked() {
-                                if idx < program_len - 1 {
-                                    to_swap = Some((idx, idx + 1));
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+
+                    match from {
+                        Colors::Palette(colors) => {
+                            ui.horizontal(|ui| {
+                                let mut to_remove = None;
+                                for (color_idx, color) in colors.iter_mut().enumerate() {
+                                    ui.color_edit_button_srgb(color);
+                                    if ui.small_button("x").clicked() {
+                                        to_remove = Some(color_idx);
+                                    }
                                 }
-                            }
-                        });
+                                if let Some(color_idx) = to_remove {
+                                    colors.remove(color_idx);
+                                }
+                                if ui.button("+").clicked() {
+                                    colors.push([255, 255, 255]);
+                                }
+                            });
+                        }
+                        Colors::Hues { min, max } => {
+                            ui.horizontal(|ui| {
+                                ui.label("hues from");
+                                ui.add(DragValue::new(min).clamp_range(0.0..=360.0).suffix("°"));
+                                ui.label("to");
+                                ui.add(DragValue::new(max).clamp_range(0.0..=360.0).suffix("°"));
+                            });
+                        }
                     }
+
+                    ui.horizontal(|ui| {
+                        ui.label("brightness");
+                        ui.add(Slider::new(brightness, 0.1..=1.0));
+                    });
                 }
-                ui.separator();
-            }
+                Action::RandomSleep { min, max } => {
+                    ui.horizontal(|ui| {
+                        ui.label("sleep between");
 
-            if let Some(idx) = to_delete {
-                self.program.remove(idx);
-            }
+                        let mut min_ms = min.as_millis() as u64;
+                        let mut max_ms = max.as_millis() as u64;
+                        ui.add(
+                            DragValue::new(&mut min_ms)
+                                .speed(10.0)
+                                .clamp_range(0..=60_000)
+                                .suffix(" ms"),
+                        );
+                        ui.label("and");
+                        ui.add(
+                            DragValue::new(&mut max_ms)
+                                .speed(10.0)
+                                .clamp_range(min_ms..=60_000)
+                                .suffix(" ms"),
+                        );
+                        *min = Duration::from_millis(min_ms);
+                        *max = Duration::from_millis(max_ms.max(min_ms));
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                }
+                Action::RandomScene { scenes, brightness } => {
+                    ui.horizontal(|ui| {
+                        ui.label("random scene");
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+
+                    ui.horizontal_wrapped(|ui| {
+                        for scene in Scene::iter() {
+                            let mut picked = scenes.contains(&scene);
+                            if ui.checkbox(&mut picked, format!("{:?}", scene)).changed() {
+                                match picked {
+                                    true => scenes.push(scene),
+                                    false => scenes.retain(|s| *s != scene),
+                                }
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("brightness");
+                        ui.add(Slider::new(brightness, 0.1..=1.0));
+                    });
+                }
+                Action::Repeat { count, body } => {
+                    ui.horizontal(|ui| {
+                        ui.label("repeat");
+                        ui.add(
+                            DragValue::new(count)
+                                .clamp_range(0..=10_000)
+                                .suffix(" times"),
+                        );
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                    ui.indent("body", |ui| actions_editor(ui, body, issues, &at));
+                }
+                Action::Block(body) => {
+                    ui.horizontal(|ui| {
+                        ui.label("block");
 
-            if let Some((idx1, idx2)) = to_swap {
-                self.program.swap(idx1, idx2);
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                    ui.indent("body", |ui| actions_editor(ui, body, issues, &at));
+                }
+                Action::Label(label) | Action::Goto(label) => {
+                    ui.horizontal(|ui| {
+                        ui.label(kind);
+                        ui.text_edit_singleline(label);
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                }
+                Action::Hold | Action::Stop => {
+                    ui.horizontal(|ui| {
+                        ui.label(kind);
+
+                        if ui.button("remove").clicked() {
+                            to_delete = Some(idx);
+                        }
+                    });
+                }
             }
 
-            ui.menu_button("add", |ui| {
-                if ui.button("sleep").clicked() {
-                    self.program.push(Action::Sleep(1));
-                    ui.close_menu();
+            ui.horizontal(|ui| {
+                if ui.button("up").clicked() && idx > 0 {
+                    to_swap = Some((idx, idx - 1));
                 }
 
-                if ui.button("set pilot").clicked() {
-                    self.program.push(Action::SetPilot(Pilot::default()));
-                    ui.close_menu();
+                if ui.button("down").clicked() && idx < program_len - 1 {
+                    to_swap = Some((idx, idx + 1));
                 }
             });
         });
+        ui.separator();
+    }
+
+    if let Some(idx) = to_delete {
+        actions.remove(idx);
     }
+
+    if let Some((idx1, idx2)) = to_swap {
+        actions.swap(idx1, idx2);
+    }
+
+    ui.menu_button("add", |ui| {
+        let added = [
+            ("sleep", Action::Sleep(Duration::from_secs(1))),
+            ("set pilot", Action::SetPilot(Pilot::default())),
+            (
+                "fade",
+                Action::Fade {
+                    to: Pilot::default(),
+                    duration_ms: 1000,
+                    easing: Easing::default(),
+                },
+            ),
+            (
+                "random color",
+                Action::RandomColor {
+                    from: Colors::Hues {
+                        min: 0.0,
+                        max: 360.0,
+                    },
+                    brightness: 1.0,
+                },
+            ),
+            (
+                "random sleep",
+                Action::RandomSleep {
+                    min: Duration::from_millis(500),
+                    max: Duration::from_secs(2),
+                },
+            ),
+            (
+                "random scene",
+                Action::RandomScene {
+                    scenes: Vec::new(),
+                    brightness: 1.0,
+                },
+            ),
+            (
+                "repeat",
+                Action::Repeat {
+                    count: 2,
+                    body: Vec::new(),
+                },
+            ),
+            ("block", Action::Block(Vec::new())),
+            ("label", Action::Label(String::from("start"))),
+            ("goto", Action::Goto(String::from("start"))),
+            ("hold", Action::Hold),
+            ("stop", Action::Stop),
+        ];
+        for (name, action) in added {
+            if ui.button(name).clicked() {
+                actions.push(action);
+                ui.close_menu();
+            }
+        }
+    });
+}
+
+/// State, color and brightness controls for a program step.
+fn pilot_editor(ui: &mut egui::Ui, p: &mut Pilot) {
+    ui.checkbox(&mut p.state, "state");
+
+    ui.horizontal(|ui| {
+        if ui.button("rgb").clicked() {
+            if p.rgb.is_none() {
+                p.rgb = Some(DEFAULT_RGB);
+            } else {
+                p.rgb = None;
+            }
+        }
+
+        if let Some(rgb) = &mut p.rgb {
+            ui.color_edit_button_rgb(rgb);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("brightness");
+        ui.add(Slider::new(&mut p.brightness, 0.1..=1.0));
+    });
 }
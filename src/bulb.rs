@@ -1,16 +1,33 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use std::ops::RangeInclusive;
+
+use crate::capabilities::Capabilities;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Bulb {
     pub ip: String,
     pub name: String,
     pub mac: String,
+    #[serde(default)]
+    pub module_name: Option<String>,
+    #[serde(default)]
+    pub fw_version: Option<String>,
+    #[serde(default)]
+    pub rssi: Option<i32>,
 }
 
 impl Bulb {
     pub fn new(ip: String, name: String, mac: String) -> Bulb {
-        Bulb { ip, name, mac }
+        Bulb {
+            ip,
+            name,
+            mac,
+            module_name: None,
+            fw_version: None,
+            rssi: None,
+        }
     }
 
     pub fn parse(ip: String, data: &str) -> Option<Bulb> {
@@ -24,16 +41,74 @@ impl Bulb {
             return None;
         }
         let v = v.unwrap();
-        let mac = v["result"]["mac"].to_string();
-        // clean up mac
-        let mac = mac.trim_start_matches('"');
-        let mac = mac.trim_end_matches('"');
-        let mac = mac.to_string();
-
-        Some(Bulb {
-            ip: ip,
-            name: mac.clone(),
-            mac: mac.clone(),
-        })
+        let mac = v["result"]["mac"].as_str()?.to_string();
+
+        let mut bulb = Bulb::new(ip, mac.clone(), mac);
+        bulb.module_name = v["result"]["moduleName"].as_str().map(String::from);
+        Some(bulb)
+    }
+
+    /// What the device supports; unrestricted when the module is unknown.
+    pub fn capabilities(&self) -> Capabilities {
+        match &self.module_name {
+            Some(module_name) => Capabilities::from_module_name(module_name),
+            None => Capabilities::all(),
+        }
+    }
+
+    pub fn supports_rgb(&self) -> bool {
+        self.capabilities().supports_rgb
+    }
+
+    /// The Kelvin range of tunable white devices.
+    pub fn supports_temp(&self) -> Option<RangeInclusive<u32>> {
+        self.capabilities().supports_temp
+    }
+
+    pub fn supports_effects(&self) -> bool {
+        self.capabilities().supports_effects
+    }
+
+    pub fn is_socket(&self) -> bool {
+        self.capabilities().is_socket
+    }
+
+    /// Applies the fields of a `getSystemConfig` or `getPilot` reply.
+    ///
+    /// Returns whether anything changed.
+    pub fn update_metadata(&mut self, data: &str) -> bool {
+        // example of data: {"method":"getSystemConfig","env":"pro","result":{"mac":"a8bb50ec140e","homeId":0,"moduleName":"ESP03_SHRGB1C_01","fwVersion":"1.25.0"}}
+        let data = data.trim_matches(char::from(0));
+        let Ok(v) = serde_json::from_str::<Value>(data) else {
+            return false;
+        };
+        let result = &v["result"];
+
+        let before = (self.module_name.clone(), self.fw_version.clone(), self.rssi);
+        if let Some(module_name) = result["moduleName"].as_str() {
+            self.module_name = Some(module_name.to_string());
+        }
+        if let Some(fw_version) = result["fwVersion"].as_str() {
+            self.fw_version = Some(fw_version.to_string());
+        }
+        if let Some(rssi) = result["rssi"].as_i64() {
+            self.rssi = Some(rssi as i32);
+        }
+        before != (self.module_name.clone(), self.fw_version.clone(), self.rssi)
+    }
+
+    /// Takes the address and device details of a freshly discovered `other`
+    /// for the same device, keeping the user-given name.
+    pub fn merge(&mut self, other: &Bulb) {
+        self.ip = other.ip.clone();
+        if other.module_name.is_some() {
+            self.module_name = other.module_name.clone();
+        }
+        if other.fw_version.is_some() {
+            self.fw_version = other.fw_version.clone();
+        }
+        if other.rssi.is_some() {
+            self.rssi = other.rssi;
+        }
     }
 }
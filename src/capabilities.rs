@@ -0,0 +1,133 @@
+use std::ops::RangeInclusive;
+
+use crate::pilot::{Pilot, PilotError, TEMP_MAX, TEMP_MIN};
+use crate::scenes::Scene;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulbKind {
+    /// Full color with tunable white, e.g. `ESP03_SHRGB1C_01`.
+    Rgb,
+    /// Tunable white only, e.g. `ESP01_SHTW1C_31`.
+    TunableWhite,
+    /// Fixed white that can only be dimmed, e.g. `ESP06_SHDW9_01`.
+    DimmableWhite,
+    /// On/off smart plug, e.g. `ESP10_SOCKET_06`.
+    Socket,
+    /// No or unrecognised module name; nothing is restricted.
+    Unknown,
+}
+
+impl BulbKind {
+    pub fn from_module_name(module_name: &str) -> BulbKind {
+        // the second `_` separated field encodes the device type
+        let kind = module_name.split('_').nth(1).unwrap_or("");
+        if kind.contains("SOCKET") {
+            BulbKind::Socket
+        } else if kind.contains("RGB") {
+            BulbKind::Rgb
+        } else if kind.contains("TW") {
+            BulbKind::TunableWhite
+        } else if kind.contains("DW") {
+            BulbKind::DimmableWhite
+        } else {
+            BulbKind::Unknown
+        }
+    }
+}
+
+/// Scenes that only use white light, offered on tunable white bulbs.
+const WHITE_SCENES: [Scene; 12] = [
+    Scene::Cozy,
+    Scene::Wakeup,
+    Scene::Bedtime,
+    Scene::WarmWhite,
+    Scene::Daylight,
+    Scene::Coolwhite,
+    Scene::Nightlight,
+    Scene::Focus,
+    Scene::Relax,
+    Scene::TVtime,
+    Scene::Candlelight,
+    Scene::Goldenwhite,
+];
+
+/// Scenes that only vary brightness, offered on dimmable white bulbs.
+const DIMMING_SCENES: [Scene; 7] = [
+    Scene::Wakeup,
+    Scene::Bedtime,
+    Scene::Nightlight,
+    Scene::Candlelight,
+    Scene::Goldenwhite,
+    Scene::Pulse,
+    Scene::Steampunk,
+];
+
+/// What a device can do, derived from the `moduleName` it reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub kind: BulbKind,
+    pub supports_rgb: bool,
+    pub supports_temp: Option<RangeInclusive<u32>>,
+    pub supports_effects: bool,
+    pub supports_dimming: bool,
+    pub is_socket: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Capabilities {
+        Capabilities::from_kind(BulbKind::Unknown)
+    }
+
+    pub fn from_module_name(module_name: &str) -> Capabilities {
+        Capabilities::from_kind(BulbKind::from_module_name(module_name))
+    }
+
+    pub fn from_kind(kind: BulbKind) -> Capabilities {
+        let (rgb, temp, effects, dimming) = match kind {
+            BulbKind::Rgb | BulbKind::Unknown => (true, Some(TEMP_MIN..=TEMP_MAX), true, true),
+            BulbKind::TunableWhite => (false, Some(2700..=TEMP_MAX), true, true),
+            BulbKind::DimmableWhite => (false, None, true, true),
+            BulbKind::Socket => (false, None, false, false),
+        };
+        Capabilities {
+            kind,
+            supports_rgb: rgb,
+            supports_temp: temp,
+            supports_effects: effects,
+            supports_dimming: dimming,
+            is_socket: kind == BulbKind::Socket,
+        }
+    }
+
+    pub fn supports_scene(&self, scene: Scene) -> bool {
+        match self.kind {
+            BulbKind::Rgb | BulbKind::Unknown => true,
+            BulbKind::TunableWhite => WHITE_SCENES.iter().any(|s| *s as u8 == scene as u8),
+            BulbKind::DimmableWhite => DIMMING_SCENES.iter().any(|s| *s as u8 == scene as u8),
+            BulbKind::Socket => false,
+        }
+    }
+
+    /// Rejects parameters of `pilot` that this device cannot apply.
+    pub fn check(&self, pilot: &Pilot) -> Result<(), PilotError> {
+        if pilot.rgb.is_some() && !self.supports_rgb {
+            return Err(PilotError::Unsupported("rgb"));
+        }
+        if (pilot.cold.is_some() || pilot.warm.is_some()) && !self.supports_rgb {
+            return Err(PilotError::Unsupported("c/w"));
+        }
+        if let Some(temp) = pilot.temp {
+            match &self.supports_temp {
+                Some(range) if range.contains(&temp) => {}
+                Some(_) => return Err(PilotError::OutOfRange("temp")),
+                None => return Err(PilotError::Unsupported("temp")),
+            }
+        }
+        if let Some(scene) = pilot.scene {
+            if !self.supports_scene(scene) {
+                return Err(PilotError::Unsupported("sceneId"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use interprocess::local_socket::LocalSocketStream;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::program::Program;
+use crate::wizard::{
+    bind_socket, bulb_addr, bulb_addrs, daemon_call, unexpected, ACK_ATTEMPTS, ACK_TIMEOUT,
+    REPLY_TIMEOUT, WIZARD_PORT,
+};
+use crate::{
+    bulb::Bulb,
+    daemon::{
+        DaemonStatus, Msg, ProgramId, ProgramInfo, Response, ResumeFrom, StepInfo, DAEMONNAME,
+    },
+    discovery::{Collector, Discovery, DISCOVERY_METHODS, METADATA_TIMEOUT},
+    error::WizardError,
+    pilot::{Method, Pilot, PilotState},
+    reply::{Replies, Reply, Subscription},
+    schedule::{Schedule, ScheduleId, ScheduleInfo},
+    solar::Location,
+};
+
+/// Async counterpart of [`crate::wizard::Wizard`] for tokio applications.
+///
+/// Replies are read by a task on the caller's runtime and routed the same way
+/// as in `Wizard`, so concurrent calls for different bulbs do not interfere.
+pub struct WizardClient {
+    socket: Arc<UdpSocket>,
+    replies: Replies,
+    reader: JoinHandle<()>,
+    daemon: Arc<Mutex<Option<LocalSocketStream>>>,
+    pub discovery: Discovery,
+}
+
+impl WizardClient {
+    /// Binds the WiZ port. Must be called from within a tokio runtime.
+    pub async fn bind() -> Result<WizardClient, WizardError> {
+        WizardClient::bind_port(WIZARD_PORT).await
+    }
+
+    /// Binds `port`, see [`crate::wizard::Wizard::bind`].
+    pub async fn bind_port(port: u16) -> Result<WizardClient, WizardError> {
+        let socket = bind_socket(port)?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket.into())?);
+
+        let replies = Replies::new();
+        let router = replies.clone();
+        let rsocket = socket.clone();
+        let reader = tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            while let Ok((amt, src)) = rsocket.recv_from(&mut buf).await {
+                let data = String::from_utf8_lossy(&buf[..amt]);
+                if let Some(reply) = Reply::parse(src.ip().to_string(), &data) {
+                    router.dispatch(reply);
+                }
+            }
+        });
+
+        Ok(WizardClient {
+            socket,
+            replies,
+            reader,
+            daemon: Arc::new(Mutex::new(None)),
+            discovery: Discovery::default(),
+        })
+    }
+
+    /// Broadcasts `getDevInfo` to [`WizardClient::discovery`] and returns
+    /// every bulb that answered in time, sweeping if none did. Each bulb is
+    /// also asked for its firmware version and signal strength.
+    pub async fn discover(&self) -> Result<Vec<Bulb>, WizardError> {
+        let broadcasts = bulb_addrs(&self.discovery.broadcast_addresses()?);
+        let sweep = bulb_addrs(&self.discovery.sweep_addresses()?);
+        let mut sub = self.replies.subscribe_async(None, &DISCOVERY_METHODS);
+
+        let data = Pilot::new(Method::GetDevInfo).build()?;
+        self.send_all(&data, &broadcasts).await?;
+
+        let mut collector = Collector::new();
+        self.collect(&mut sub, &mut collector).await;
+        if collector.bulbs.is_empty() && !sweep.is_empty() {
+            let _ = self.send_all(&data, &sweep).await;
+            self.collect(&mut sub, &mut collector).await;
+        }
+
+        Ok(collector.bulbs)
+    }
+
+    async fn collect(
+        &self,
+        sub: &mut Subscription<UnboundedReceiver<Reply>>,
+        collector: &mut Collector,
+    ) {
+        let mut deadline = Instant::now() + REPLY_TIMEOUT;
+        while let Ok(Some(reply)) = timeout_at(deadline, sub.rx.recv()).await {
+            if let Some((bulb, true)) = collector.handle(&reply) {
+                if let Ok(addr) = bulb_addr(&bulb.ip) {
+                    for method in [Method::GetSystemConfig, Method::GetPilot] {
+                        let query = Pilot::new(method).build().unwrap();
+                        let _ = self.socket.send_to(query.as_bytes(), addr).await;
+                    }
+                }
+                deadline = deadline.max(Instant::now() + METADATA_TIMEOUT);
+            }
+        }
+    }
+
+    async fn send_all(&self, data: &str, addrs: &[SocketAddr]) -> Result<(), WizardError> {
+        let mut last_err = None;
+        let mut sent = false;
+        for addr in addrs.iter() {
+            match self.socket.send_to(data.as_bytes(), addr).await {
+                Ok(_) => sent = true,
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) if !sent => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn set_pilot(&self, bulb: &Bulb, pilot: &Pilot) -> Result<(), WizardError> {
+        let data = pilot.build_for(&bulb.capabilities())?;
+        let addr = bulb_addr(&bulb.ip)?;
+        self.socket.send_to(data.as_bytes(), addr).await?;
+        Ok(())
+    }
+
+    /// Sends the pilot and waits for the bulb to acknowledge it, looking the
+    /// bulb up by MAC if it stays silent, see
+    /// [`crate::wizard::Wizard::set_pilot_ack`].
+    pub async fn set_pilot_ack(&self, bulb: &mut Bulb, pilot: &Pilot) -> Result<(), WizardError> {
+        match self.send_ack(bulb, pilot).await {
+            Err(WizardError::Timeout) | Err(WizardError::Io(_)) => {
+                self.resolve(bulb).await?;
+                self.send_ack(bulb, pilot).await
+            }
+            result => result,
+        }
+    }
+
+    /// Finds the bulb with the same MAC and takes its current address.
+    pub async fn resolve(&self, bulb: &mut Bulb) -> Result<(), WizardError> {
+        let broadcasts = bulb_addrs(&self.discovery.broadcast_addresses()?);
+        let sweep = bulb_addrs(&self.discovery.sweep_addresses()?);
+
+        let mut sub = self.replies.subscribe_async(None, &[Method::GetDevInfo]);
+        let data = Pilot::new(Method::GetDevInfo).build()?;
+
+        for round in [broadcasts, sweep] {
+            if round.is_empty() {
+                continue;
+            }
+            self.send_all(&data, &round).await?;
+
+            let deadline = Instant::now() + REPLY_TIMEOUT;
+            while let Ok(Some(reply)) = timeout_at(deadline, sub.rx.recv()).await {
+                if let Some(found) = Bulb::parse(reply.ip, &reply.data) {
+                    if found.mac == bulb.mac {
+                        bulb.merge(&found);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Err(WizardError::NotFound(bulb.mac.clone()))
+    }
+
+    async fn send_ack(&self, bulb: &Bulb, pilot: &Pilot) -> Result<(), WizardError> {
+        let data = pilot.build_for(&bulb.capabilities())?;
+        let addr = bulb_addr(&bulb.ip)?;
+
+        let mut sub = self
+            .replies
+            .subscribe_async(Some(bulb.ip.clone()), std::slice::from_ref(&pilot.method));
+
+        let mut wait = ACK_TIMEOUT;
+        for _ in 0..ACK_ATTEMPTS {
+            self.socket.send_to(data.as_bytes(), addr).await?;
+
+            if let Ok(Some(reply)) = timeout(wait, sub.rx.recv()).await {
+                return reply.ack().map_err(WizardError::Rejected);
+            }
+            wait *= 2;
+        }
+
+        Err(WizardError::Timeout)
+    }
+
+    pub async fn get_pilot(&self, bulb: &Bulb) -> Result<PilotState, WizardError> {
+        let pilot = Pilot::new(Method::GetPilot);
+        let addr = bulb_addr(&bulb.ip)?;
+
+        let mut sub = self
+            .replies
+            .subscribe_async(Some(bulb.ip.clone()), &[Method::GetPilot]);
+        self.socket.send_to(pilot.build()?.as_bytes(), addr).await?;
+
+        let reply = timeout(REPLY_TIMEOUT, sub.rx.recv())
+            .await
+            .ok()
+            .flatten()
+            .ok_or(WizardError::Timeout)?;
+        PilotState::parse(&reply.data)
+            .ok_or_else(|| WizardError::Rejected(String::from("unreadable getPilot reply")))
+    }
+
+    pub async fn daemon_connect(&self) -> Result<(), WizardError> {
+        let stream = tokio::task::spawn_blocking(|| LocalSocketStream::connect(DAEMONNAME))
+            .await
+            .map_err(|e| WizardError::Io(e.into()))??;
+        *self.daemon.lock().await = Some(stream);
+        Ok(())
+    }
+
+    pub async fn daemon_shutdown(&self) -> Result<(), WizardError> {
+        let stream = self.daemon.lock().await.take();
+        let stream = stream.ok_or(WizardError::DaemonNotConnected)?;
+        match Self::daemon_write(stream, Msg::Stop).await?.1? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Checks that the daemon is still there and answering.
+    pub async fn daemon_ping(&self) -> Result<(), WizardError> {
+        match self.daemon_request(Msg::Ping).await? {
+            Response::Pong => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_status(&self) -> Result<DaemonStatus, WizardError> {
+        match self.daemon_request(Msg::Status).await? {
+            Response::Status(status) => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs `program` on `bulb`, replacing the program it ran before.
+    pub async fn daemon_run_program(
+        &self,
+        program: Program,
+        bulb: Bulb,
+    ) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::Run(program, bulb)).await? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs `program` on every bulb in `bulbs` in step.
+    pub async fn daemon_run_group(
+        &self,
+        program: Program,
+        bulbs: Vec<Bulb>,
+    ) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::RunGroup(program, bulbs)).await? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_list_programs(&self) -> Result<Vec<ProgramInfo>, WizardError> {
+        match self.daemon_request(Msg::ListPrograms).await? {
+            Response::Programs(programs) => Ok(programs),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_current_step(&self, id: ProgramId) -> Result<StepInfo, WizardError> {
+        match self.daemon_request(Msg::CurrentStep(id)).await? {
+            Response::Step(step) => Ok(step),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_pause(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::Pause(id)).await
+    }
+
+    pub async fn daemon_resume(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::Resume(id)).await
+    }
+
+    pub async fn daemon_stop_program(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::StopProgram(id)).await
+    }
+
+    /// Chooses where the program picks up when the daemon restarts.
+    pub async fn daemon_set_resume(
+        &self,
+        id: ProgramId,
+        resume: ResumeFrom,
+    ) -> Result<(), WizardError> {
+        self.daemon_done(Msg::SetResume(id, resume)).await
+    }
+
+    pub async fn daemon_add_schedule(&self, schedule: Schedule) -> Result<ScheduleId, WizardError> {
+        match self.daemon_request(Msg::AddSchedule(schedule)).await? {
+            Response::Scheduled(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_list_schedules(&self) -> Result<Vec<ScheduleInfo>, WizardError> {
+        match self.daemon_request(Msg::ListSchedules).await? {
+            Response::Schedules(schedules) => Ok(schedules),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn daemon_remove_schedule(&self, id: ScheduleId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::RemoveSchedule(id)).await
+    }
+
+    /// Where the bulbs are, for schedules that follow the sun.
+    pub async fn daemon_set_location(&self, location: Option<Location>) -> Result<(), WizardError> {
+        self.daemon_done(Msg::SetLocation(location)).await
+    }
+
+    /// When `schedule` would next start, in local time.
+    pub async fn daemon_preview(
+        &self,
+        schedule: Schedule,
+        count: usize,
+    ) -> Result<Vec<NaiveDateTime>, WizardError> {
+        match self.daemon_request(Msg::Preview(schedule, count)).await? {
+            Response::Preview(starts) => Ok(starts),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs a program written in the [`crate::script`] format on `bulbs`.
+    pub async fn daemon_import(
+        &self,
+        text: String,
+        bulbs: Vec<Bulb>,
+    ) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::Import(text, bulbs)).await? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// A running program in the [`crate::script`] format.
+    pub async fn daemon_export(&self, id: ProgramId) -> Result<String, WizardError> {
+        match self.daemon_request(Msg::Export(id)).await? {
+            Response::Script(text) => Ok(text),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn daemon_done(&self, msg: Msg) -> Result<(), WizardError> {
+        match self.daemon_request(msg).await? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn daemon_request(&self, msg: Msg) -> Result<Response, WizardError> {
+        let mut daemon = self.daemon.lock().await;
+        let stream = daemon.take().ok_or(WizardError::DaemonNotConnected)?;
+        // on failure the stream is dropped, so the daemon reads as disconnected
+        let (stream, response) = Self::daemon_write(stream, msg).await?;
+        *daemon = Some(stream);
+        response
+    }
+
+    /// Sends `msg` and reads the answer on a blocking thread, handing the
+    /// stream back for reuse unless the connection failed.
+    async fn daemon_write(
+        mut stream: LocalSocketStream,
+        msg: Msg,
+    ) -> Result<(LocalSocketStream, Result<Response, WizardError>), WizardError> {
+        tokio::task::spawn_blocking(move || match daemon_call(&mut stream, msg) {
+            Err(e @ WizardError::Io(_)) | Err(e @ WizardError::Serialize(_)) => Err(e),
+            response => Ok((stream, response)),
+        })
+        .await
+        .map_err(|e| WizardError::Io(e.into()))?
+    }
+}
+
+impl Drop for WizardClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
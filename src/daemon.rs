@@ -1,12 +1,261 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
 
-use crate::program::Action;
+use crate::bulb::Bulb;
+use crate::program::{Action, Program};
+use crate::schedule::{Schedule, ScheduleId, ScheduleInfo};
+use crate::solar::Location;
+
+pub const DAEMONNAME: &str = "wizarddaemon";
+
+/// Bumped whenever [`Msg`] or [`Response`] change incompatibly.
+///
+/// 2: `Run` takes a [`Program`] and a [`Bulb`], and sleeps are in
+/// milliseconds.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Largest frame either side accepts, far above any real program.
+pub const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// Writes `data` as one frame: its length as a big-endian `u32`, then the
+/// bytes themselves.
+pub fn write_frame(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(data.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(data);
+    w.write_all(&frame)?;
+    w.flush()
+}
+
+/// Reads one frame written by [`write_frame`]. Returns `None` when the peer
+/// closed the connection between frames.
+pub fn read_frame(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match r.read(&mut len[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+    let mut data = vec![0u8; len as usize];
+    r.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Identifies a program running in the daemon.
+pub type ProgramId = u64;
+
+/// What a client sends; every request is answered with one [`Response`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub msg: Msg,
+}
+
+impl Request {
+    pub fn new(msg: Msg) -> Request {
+        Request {
+            version: PROTOCOL_VERSION,
+            msg,
+        }
+    }
+
+    /// Decodes one request, checking its protocol version before the rest,
+    /// so that older clients are told why they are not understood.
+    pub fn decode(data: &[u8]) -> Result<Msg, DaemonError> {
+        let value: serde_json::Value =
+            serde_json::from_slice(data).map_err(|e| DaemonError::Malformed(e.to_string()))?;
+
+        // requests from before versioning have no version at all
+        let version = value["version"].as_u64().unwrap_or(0) as u32;
+        if version != PROTOCOL_VERSION {
+            return Err(DaemonError::Version {
+                daemon: PROTOCOL_VERSION,
+                client: version,
+            });
+        }
+        serde_json::from_value::<Request>(value)
+            .map(|request| request.msg)
+            .map_err(|e| DaemonError::Malformed(e.to_string()))
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Msg {
+    /// Answered with [`Response::Pong`].
+    Ping,
+    /// Answered with [`Response::Status`].
+    Status,
+    /// Shuts the daemon down.
     Stop,
-    Run(Vec<Action>, String),
-    Ignore,
+    /// Starts a program on one bulb, replacing whatever ran on it before.
+    /// Answered with [`Response::Started`].
+    Run(Program, Bulb),
+    /// Starts a program that drives every bulb in the group in step.
+    /// Answered with [`Response::Started`].
+    RunGroup(Program, Vec<Bulb>),
+    /// Answered with [`Response::Programs`].
+    ListPrograms,
+    /// Answered with [`Response::Step`].
+    CurrentStep(ProgramId),
+    Pause(ProgramId),
+    Resume(ProgramId),
+    StopProgram(ProgramId),
+    /// Chooses where the program picks up after the daemon restarts.
+    SetResume(ProgramId, ResumeFrom),
+    /// Answered with [`Response::Scheduled`].
+    AddSchedule(Schedule),
+    /// Answered with [`Response::Schedules`].
+    ListSchedules,
+    RemoveSchedule(ScheduleId),
+    /// Where the bulbs are, for schedules that follow the sun.
+    SetLocation(Option<Location>),
+    /// Lists when a schedule would next start, without adding it.
+    /// Answered with [`Response::Preview`].
+    Preview(Schedule, usize),
+    /// Starts a program written in the [`crate::script`] format on the
+    /// bulbs. Answered with [`Response::Started`].
+    Import(String, Vec<Bulb>),
+    /// A running program in the [`crate::script`] format. Answered with
+    /// [`Response::Script`].
+    Export(ProgramId),
 }
 
-pub const DAEMONNAME: &str = "wizarddaemon";
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    Pong,
+    Status(DaemonStatus),
+    Started(ProgramId),
+    Programs(Vec<ProgramInfo>),
+    Step(StepInfo),
+    Scheduled(ScheduleId),
+    Schedules(Vec<ScheduleInfo>),
+    /// Start times in local time.
+    Preview(Vec<NaiveDateTime>),
+    Script(String),
+    /// The request was carried out and has nothing to report.
+    Done,
+    Error(DaemonError),
+}
+
+/// The daemon's answer to a [`Request`], tagged with its protocol version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Answer {
+    pub version: u32,
+    pub response: Response,
+}
+
+impl Answer {
+    pub fn new(response: Response) -> Answer {
+        Answer {
+            version: PROTOCOL_VERSION,
+            response,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    /// Version of the daemon binary.
+    pub version: String,
+    pub uptime_secs: u64,
+    pub programs: usize,
+    /// Whether bulbs push their state changes to the daemon.
+    pub push: bool,
+    #[serde(default)]
+    pub location: Option<Location>,
+}
+
+/// A program as reported by [`Response::Programs`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramInfo {
+    pub id: ProgramId,
+    pub bulbs: Vec<Bulb>,
+    /// Index of the action being run.
+    pub step: usize,
+    pub len: usize,
+    pub paused: bool,
+    /// Why the last step did not reach a bulb, cleared once one does.
+    #[serde(default)]
+    pub error: Option<DaemonError>,
+    #[serde(default)]
+    pub resume: ResumeFrom,
+}
+
+/// Where a program picks up when the daemon restores it after a restart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResumeFrom {
+    /// The step it was on when the daemon went down.
+    #[default]
+    LastStep,
+    /// Its first step.
+    Start,
+}
+
+/// The action a program is on, as reported by [`Response::Step`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepInfo {
+    pub id: ProgramId,
+    pub step: usize,
+    pub action: Action,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DaemonError {
+    /// The request could not be decoded.
+    Malformed(String),
+    /// The client speaks another protocol version.
+    Version {
+        daemon: u32,
+        client: u32,
+    },
+    UnknownProgram(ProgramId),
+    UnknownSchedule(ScheduleId),
+    /// The program does not parse or compile, e.g. a `Goto` has no label, or
+    /// [`crate::program::validate`] found errors in it.
+    InvalidProgram(String),
+    /// A bulb did not acknowledge a step.
+    Unreachable {
+        mac: String,
+        message: String,
+    },
+    /// The daemon answered with something other than what was asked for.
+    Unexpected(String),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::Malformed(e) => write!(f, "malformed request: {}", e),
+            DaemonError::Version { daemon, client } => write!(
+                f,
+                "daemon speaks protocol {}, client speaks {}",
+                daemon, client
+            ),
+            DaemonError::UnknownProgram(id) => write!(f, "no program #{}", id),
+            DaemonError::UnknownSchedule(id) => write!(f, "no schedule #{}", id),
+            DaemonError::InvalidProgram(e) => write!(f, "invalid program: {}", e),
+            DaemonError::Unreachable { mac, message } => write!(f, "{}: {}", mac, message),
+            DaemonError::Unexpected(response) => write!(f, "unexpected response {}", response),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
@@ -0,0 +1,169 @@
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use std::net::Ipv4Addr;
+
+use crate::bulb::Bulb;
+use crate::error::WizardError;
+use crate::pilot::Method;
+use crate::reply::Reply;
+
+/// Methods whose replies make up a discovery run.
+pub(crate) const DISCOVERY_METHODS: [Method; 3] = [
+    Method::GetDevInfo,
+    Method::GetSystemConfig,
+    Method::GetPilot,
+];
+
+/// How long to wait for device details after a bulb first answers.
+pub(crate) const METADATA_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Largest range [`Discovery::sweep`] will probe host by host.
+pub const MAX_SWEEP_PREFIX: u8 = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiscoveryTarget {
+    /// The broadcast address of every IPv4 interface, using its real netmask.
+    Interfaces,
+    /// The broadcast address of a subnet, e.g. an IoT VLAN.
+    Subnet(Ipv4Net),
+    /// An address sent to as-is, such as a directed broadcast.
+    Address(Ipv4Addr),
+}
+
+/// Where `getDevInfo` is sent when looking for bulbs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub targets: Vec<DiscoveryTarget>,
+    /// Ranges probed host by host when no bulb answers the broadcasts,
+    /// for networks that filter broadcast traffic.
+    #[serde(default)]
+    pub sweep: Vec<Ipv4Net>,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            targets: vec![DiscoveryTarget::Interfaces],
+            sweep: Vec::new(),
+        }
+    }
+}
+
+impl Discovery {
+    /// Resolves the targets into the addresses to broadcast to.
+    pub fn broadcast_addresses(&self) -> Result<Vec<Ipv4Addr>, WizardError> {
+        let mut addrs: Vec<Ipv4Addr> = Vec::new();
+        for target in self.targets.iter() {
+            match target {
+                DiscoveryTarget::Interfaces => {
+                    let networks = local_networks()?;
+                    if networks.is_empty() {
+                        return Err(WizardError::NoLocalIp(String::from(
+                            "no IPv4 interface is up",
+                        )));
+                    }
+                    addrs.extend(networks.iter().map(|net| net.broadcast()));
+                }
+                DiscoveryTarget::Subnet(net) => addrs.push(net.broadcast()),
+                DiscoveryTarget::Address(addr) => addrs.push(*addr),
+            }
+        }
+        addrs.sort();
+        addrs.dedup();
+        Ok(addrs)
+    }
+
+    /// Every host in the sweep ranges.
+    pub fn sweep_addresses(&self) -> Result<Vec<Ipv4Addr>, WizardError> {
+        let mut addrs: Vec<Ipv4Addr> = Vec::new();
+        for net in self.sweep.iter() {
+            if net.prefix_len() < MAX_SWEEP_PREFIX {
+                return Err(WizardError::InvalidAddress(format!(
+                    "{} is too large to sweep",
+                    net
+                )));
+            }
+            addrs.extend(net.hosts());
+        }
+        Ok(addrs)
+    }
+}
+
+/// The non-loopback IPv4 networks this host is attached to.
+pub fn local_networks() -> Result<Vec<Ipv4Net>, WizardError> {
+    let mut networks = Vec::new();
+    for iface in if_addrs::get_if_addrs()? {
+        if iface.is_loopback() {
+            continue;
+        }
+        if let if_addrs::IfAddr::V4(addr) = iface.addr {
+            if let Ok(net) = Ipv4Net::with_netmask(addr.ip, addr.netmask) {
+                networks.push(net);
+            }
+        }
+    }
+    Ok(networks)
+}
+
+/// Our address on the network that `ip` is on, falling back to any
+/// non-loopback address.
+pub fn local_address_for(ip: Ipv4Addr) -> Option<Ipv4Addr> {
+    let networks = local_networks().ok()?;
+    networks
+        .iter()
+        .find(|net| net.contains(&ip))
+        .or(networks.first())
+        .map(|net| net.addr())
+}
+
+/// Every IPv4 address of this host, used to drop our own broadcasts.
+pub fn local_addresses() -> Vec<Ipv4Addr> {
+    if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(addr) => Some(addr.ip),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Turns the replies of one discovery run into bulb updates.
+pub(crate) struct Collector {
+    local: Vec<Ipv4Addr>,
+    pub bulbs: Vec<Bulb>,
+}
+
+impl Collector {
+    pub(crate) fn new() -> Collector {
+        Collector {
+            local: local_addresses(),
+            bulbs: Vec::new(),
+        }
+    }
+
+    /// Returns the bulb the reply added or changed, and whether it is new.
+    pub(crate) fn handle(&mut self, reply: &Reply) -> Option<(Bulb, bool)> {
+        if self.local.iter().any(|local| local.to_string() == reply.ip) {
+            return None;
+        }
+
+        if reply.method == Method::GetDevInfo.to_string() {
+            let bulb = Bulb::parse(reply.ip.clone(), &reply.data)?;
+            if self.bulbs.iter().any(|b| b.mac == bulb.mac) {
+                // the same bulb answering a broadcast on a second interface
+                return None;
+            }
+            self.bulbs.push(bulb.clone());
+            return Some((bulb, true));
+        }
+
+        let bulb = self.bulbs.iter_mut().find(|b| b.ip == reply.ip)?;
+        if bulb.update_metadata(&reply.data) {
+            Some((bulb.clone(), false))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fmt, io};
+
+use crate::daemon::DaemonError;
+use crate::pilot::PilotError;
+
+#[derive(Debug)]
+pub enum WizardError {
+    /// The UDP socket could not be bound, usually because another WiZ tool
+    /// already holds the port.
+    Bind(u16, io::Error),
+    Io(io::Error),
+    /// No local IPv4 address to derive a broadcast address from.
+    NoLocalIp(String),
+    InvalidAddress(String),
+    Pilot(PilotError),
+    /// The bulb answered with an `error` object or without `success`.
+    Rejected(String),
+    /// No reply after every retry.
+    Timeout,
+    /// No bulb with this MAC answered a discovery.
+    NotFound(String),
+    DaemonNotConnected,
+    /// The daemon refused a request or answered something unexpected.
+    Daemon(DaemonError),
+    Serialize(serde_json::Error),
+}
+
+impl fmt::Display for WizardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WizardError::Bind(port, e) => write!(f, "could not bind port {}: {}", port, e),
+            WizardError::Io(e) => write!(f, "socket error: {}", e),
+            WizardError::NoLocalIp(e) => write!(f, "no local ip address: {}", e),
+            WizardError::InvalidAddress(addr) => write!(f, "invalid address: {}", addr),
+            WizardError::Pilot(e) => write!(f, "invalid pilot: {}", e),
+            WizardError::Rejected(message) => write!(f, "bulb rejected pilot: {}", message),
+            WizardError::Timeout => write!(f, "bulb did not answer"),
+            WizardError::NotFound(mac) => write!(f, "no bulb with mac {} answered", mac),
+            WizardError::DaemonNotConnected => write!(f, "daemon not connected"),
+            WizardError::Daemon(e) => write!(f, "daemon: {}", e),
+            WizardError::Serialize(e) => write!(f, "could not encode message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WizardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WizardError::Bind(_, e) | WizardError::Io(e) => Some(e),
+            WizardError::Pilot(e) => Some(e),
+            WizardError::Serialize(e) => Some(e),
+            WizardError::Daemon(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WizardError {
+    fn from(e: io::Error) -> Self {
+        WizardError::Io(e)
+    }
+}
+
+impl From<PilotError> for WizardError {
+    fn from(e: PilotError) -> Self {
+        WizardError::Pilot(e)
+    }
+}
+
+impl From<serde_json::Error> for WizardError {
+    fn from(e: serde_json::Error) -> Self {
+        WizardError::Serialize(e)
+    }
+}
@@ -1,6 +1,17 @@
 pub mod bulb;
+pub mod capabilities;
+#[cfg(feature = "async")]
+pub mod client;
 pub mod daemon;
+pub mod discovery;
+pub mod error;
 pub mod pilot;
 pub mod program;
+pub mod push;
+pub mod reply;
 pub mod scenes;
+pub mod schedule;
+pub mod script;
+pub mod sim;
+pub mod solar;
 pub mod wizard;
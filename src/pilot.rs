@@ -1,23 +1,58 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use std::fmt;
 
+use crate::capabilities::Capabilities;
 use crate::scenes::Scene;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Method {
     SetPilot,
+    GetPilot,
     GetDevInfo,
+    GetSystemConfig,
 }
 
-impl Method {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Method::SetPilot => "setPilot",
+            Method::GetPilot => "getPilot",
+            Method::GetDevInfo => "getDevInfo",
+            Method::GetSystemConfig => "getSystemConfig",
+        };
+        f.write_str(name)
+    }
+}
+
+pub const TEMP_MIN: u32 = 2200;
+pub const TEMP_MAX: u32 = 6500;
+/// Blue, where color pickers start before a color is chosen.
+pub const DEFAULT_RGB: [f32; 3] = [0.0, 0.0, 1.0];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PilotError {
+    /// Two parameters that the bulb cannot apply together.
+    Conflict(&'static str, &'static str),
+    OutOfRange(&'static str),
+    /// A parameter the target device has no hardware for.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for PilotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Method::SetPilot => String::from("setPilot"),
-            Method::GetDevInfo => String::from("getDevInfo"),
+            PilotError::Conflict(a, b) => write!(f, "{} cannot be combined with {}", a, b),
+            PilotError::OutOfRange(param) => write!(f, "{} is out of range", param),
+            PilotError::Unsupported(param) => {
+                write!(f, "{} is not supported by this device", param)
+            }
         }
     }
 }
 
+impl std::error::Error for PilotError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pilot {
     pub method: Method,
@@ -26,17 +61,26 @@ pub struct Pilot {
     pub scene: Option<Scene>,
     pub brightness: f32, // 10-100
     pub speed: f32,      // 20-200
+    #[serde(default)]
+    pub temp: Option<u32>, // 2200-6500 Kelvin
+    #[serde(default)]
+    pub cold: Option<u8>,
+    #[serde(default)]
+    pub warm: Option<u8>,
 }
 
 impl Pilot {
     pub fn new(method: Method) -> Pilot {
         Pilot {
-            method: method,
+            method,
             state: true,
             rgb: None,
             scene: None,
             brightness: 1.0,
             speed: 0.9,
+            temp: None,
+            cold: None,
+            warm: None,
         }
     }
 
@@ -64,7 +108,76 @@ impl Pilot {
         self.speed = speed;
     }
 
-    pub fn build(&self) -> String {
+    pub fn set_temp(&mut self, temp: u32) {
+        self.temp = Some(temp);
+    }
+
+    pub fn set_cold(&mut self, cold: u8) {
+        self.cold = Some(cold);
+    }
+
+    pub fn set_warm(&mut self, warm: u8) {
+        self.warm = Some(warm);
+    }
+
+    /// The `dimming` percentage sent to the bulb.
+    pub fn dimming(&self) -> u8 {
+        (self.brightness * 100.0).round() as u8
+    }
+
+    /// The effect `speed` percentage sent to the bulb.
+    pub fn speed_percent(&self) -> u8 {
+        (self.speed * 100.0).round() as u8
+    }
+
+    /// Rejects parameter combinations the bulb would refuse or misapply.
+    pub fn validate(&self) -> Result<(), PilotError> {
+        let white = self.cold.is_some() || self.warm.is_some();
+
+        if self.state && !(10..=100).contains(&self.dimming()) {
+            return Err(PilotError::OutOfRange("dimming"));
+        }
+
+        if let Some(temp) = self.temp {
+            if !(TEMP_MIN..=TEMP_MAX).contains(&temp) {
+                return Err(PilotError::OutOfRange("temp"));
+            }
+            if self.rgb.is_some() {
+                return Err(PilotError::Conflict("temp", "rgb"));
+            }
+            if white {
+                return Err(PilotError::Conflict("temp", "c/w"));
+            }
+        }
+
+        if self.scene.is_some() {
+            if self.rgb.is_some() {
+                return Err(PilotError::Conflict("sceneId", "rgb"));
+            }
+            if self.temp.is_some() {
+                return Err(PilotError::Conflict("sceneId", "temp"));
+            }
+            if white {
+                return Err(PilotError::Conflict("sceneId", "c/w"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<String, PilotError> {
+        self.build_for(&Capabilities::all())
+    }
+
+    /// Builds the request for a device with `caps`, refusing parameters it
+    /// does not support and leaving out dimming and speed where they do not
+    /// apply.
+    pub fn build_for(&self, caps: &Capabilities) -> Result<String, PilotError> {
+        if self.method == Method::SetPilot {
+            self.validate()?;
+            caps.check(self)?;
+        }
+
         let mut map = Map::new();
         map.insert(
             String::from("method"),
@@ -72,31 +185,44 @@ impl Pilot {
         );
 
         match self.method {
-            Method::GetDevInfo => {}
+            Method::GetDevInfo | Method::GetPilot | Method::GetSystemConfig => {}
             Method::SetPilot => {
                 let mut params = Map::new();
                 params.insert(String::from("state"), Value::Bool(self.state));
 
-                if self.state {
+                if self.state && caps.supports_dimming {
                     params.insert(
                         String::from("dimming"),
-                        Value::Number(((self.brightness * 100.0) as u8).into()),
+                        Value::Number(self.dimming().into()),
                     );
                 }
                 if let Some([r, g, b]) = self.rgb {
-                    let r = (r * 255.0) as u8;
-                    let g = (g * 255.0) as u8;
-                    let b = (b * 255.0) as u8;
+                    // rounded, so fade frames land on the nearest value
+                    let r = (r * 255.0).round() as u8;
+                    let g = (g * 255.0).round() as u8;
+                    let b = (b * 255.0).round() as u8;
 
                     params.insert(String::from("r"), Value::Number(r.into()));
                     params.insert(String::from("g"), Value::Number(g.into()));
                     params.insert(String::from("b"), Value::Number(b.into()));
                 }
 
-                params.insert(
-                    String::from("speed"),
-                    Value::Number(((self.speed * 100.0) as i32).into()),
-                );
+                if let Some(temp) = self.temp {
+                    params.insert(String::from("temp"), Value::Number(temp.into()));
+                }
+                if let Some(cold) = self.cold {
+                    params.insert(String::from("c"), Value::Number(cold.into()));
+                }
+                if let Some(warm) = self.warm {
+                    params.insert(String::from("w"), Value::Number(warm.into()));
+                }
+
+                if caps.supports_effects {
+                    params.insert(
+                        String::from("speed"),
+                        Value::Number(self.speed_percent().into()),
+                    );
+                }
 
                 if let Some(scene) = self.scene {
                     params.insert(
@@ -110,7 +236,7 @@ impl Pilot {
         }
 
         let json = Value::Object(map);
-        json.to_string()
+        Ok(json.to_string())
     }
 }
 
@@ -119,3 +245,93 @@ impl Default for Pilot {
         Pilot::new(Method::SetPilot)
     }
 }
+
+/// State reported by a bulb in reply to `getPilot`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PilotState {
+    pub state: bool,
+    #[serde(default)]
+    pub dimming: Option<u8>,
+    #[serde(default)]
+    pub r: Option<u8>,
+    #[serde(default)]
+    pub g: Option<u8>,
+    #[serde(default)]
+    pub b: Option<u8>,
+    #[serde(default)]
+    pub temp: Option<u32>,
+    #[serde(default)]
+    pub c: Option<u8>,
+    #[serde(default)]
+    pub w: Option<u8>,
+    #[serde(default, rename = "sceneId")]
+    pub scene_id: Option<u8>,
+    #[serde(default)]
+    pub speed: Option<u8>,
+    #[serde(default)]
+    pub rssi: Option<i32>,
+}
+
+impl PilotState {
+    pub fn parse(data: &str) -> Option<PilotState> {
+        // example of data: {"method":"getPilot","env":"pro","result":{"mac":"a8bb50ec140e","rssi":-62,"state":true,"sceneId":0,"r":255,"g":0,"b":0,"dimming":80}}
+        let data = data.trim_matches(char::from(0));
+        let v: Value = serde_json::from_str(data).ok()?;
+        if v["method"] != "getPilot" {
+            return None;
+        }
+        serde_json::from_value(v["result"].clone()).ok()
+    }
+
+    /// Parses a `syncPilot` push, returning the sender's MAC with the state.
+    pub fn parse_sync(data: &str) -> Option<(String, PilotState)> {
+        // example of data: {"method":"syncPilot","env":"pro","params":{"mac":"a8bb50ec140e","rssi":-60,"src":"udp","state":true,"sceneId":0,"temp":2700,"dimming":100}}
+        let data = data.trim_matches(char::from(0));
+        let v: Value = serde_json::from_str(data).ok()?;
+        if v["method"] != "syncPilot" {
+            return None;
+        }
+        let mac = v["params"]["mac"].as_str()?.to_string();
+        let state = serde_json::from_value(v["params"].clone()).ok()?;
+        Some((mac, state))
+    }
+
+    pub fn rgb(&self) -> Option<[u8; 3]> {
+        match (self.r, self.g, self.b) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => None,
+        }
+    }
+
+    pub fn scene(&self) -> Option<Scene> {
+        self.scene_id.and_then(Scene::from_id)
+    }
+
+    /// Builds a `setPilot` that reproduces this state.
+    pub fn to_pilot(&self) -> Pilot {
+        let mut pilot = Pilot::new(Method::SetPilot);
+        pilot.set_state(self.state);
+        if let Some(dimming) = self.dimming {
+            pilot.set_brightness(dimming as f32 / 100.0);
+        }
+        if let Some([r, g, b]) = self.rgb() {
+            pilot.set_rgb(r, g, b);
+        }
+        if let Some(temp) = self.temp {
+            pilot.set_temp(temp);
+        }
+        if let Some(cold) = self.c {
+            pilot.set_cold(cold);
+        }
+        if let Some(warm) = self.w {
+            pilot.set_warm(warm);
+        }
+        if let Some(scene) = self.scene() {
+            pilot.set_scene(scene);
+        }
+        if let Some(speed) = self.speed {
+            pilot.set_speed(speed as f32 / 100.0);
+        }
+        pilot
+    }
+}
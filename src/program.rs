@@ -1,8 +1,803 @@
-use crate::pilot::Pilot;
-use serde::{Deserialize, Serialize};
+use crate::capabilities::Capabilities;
+use crate::pilot::{Method, Pilot, PilotError};
+use crate::scenes::Scene;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Lowest brightness a bulb that is on can show; fades from or to off pass
+/// through it.
+const MIN_BRIGHTNESS: f32 = 0.1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
-    Sleep(u64),
+    #[serde(with = "sleep")]
+    Sleep(Duration),
     SetPilot(Pilot),
+    /// Moves brightness, rgb, Kelvin and c/w from the bulbs' current state to
+    /// `to` over `duration_ms`. Scenes cannot be blended and switch at the end.
+    Fade {
+        to: Pilot,
+        duration_ms: u64,
+        #[serde(default)]
+        easing: Easing,
+    },
+    /// Sets the bulbs to a color picked at random.
+    RandomColor {
+        from: Colors,
+        #[serde(default = "full")]
+        brightness: f32,
+    },
+    /// Sleeps for a random time between `min` and `max`.
+    RandomSleep {
+        #[serde(with = "sleep")]
+        min: Duration,
+        #[serde(with = "sleep")]
+        max: Duration,
+    },
+    /// Sets the bulbs to one of `scenes`, picked at random.
+    RandomScene {
+        scenes: Vec<Scene>,
+        #[serde(default = "full")]
+        brightness: f32,
+    },
+    /// Runs `body` `count` times in a row.
+    Repeat {
+        count: u32,
+        body: Vec<Action>,
+    },
+    /// Groups actions, e.g. to jump past them with a [`Action::Goto`].
+    Block(Vec<Action>),
+    /// Marks a place to [`Action::Goto`], anywhere in the program.
+    Label(String),
+    Goto(String),
+    /// Keeps the bulbs as they are until the program is stopped.
+    Hold,
+    /// Ends the program, leaving the bulbs as they are.
+    Stop,
+}
+
+fn full() -> f32 {
+    1.0
+}
+
+/// Where a [`Action::RandomColor`] picks its color from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Colors {
+    Palette(Vec<[u8; 3]>),
+    /// Fully saturated hues from `min` to `max` degrees, wrapping past 360
+    /// when `min` is larger, e.g. 300 to 60 for purples, reds and oranges.
+    Hues {
+        min: f32,
+        max: f32,
+    },
+}
+
+/// The fully saturated color of `hue` degrees.
+fn hue_rgb(hue: f32) -> [u8; 3] {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let [r, g, b] = match hue as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    };
+    [r, g, b].map(|c: f32| (c * 255.0).round() as u8)
+}
+
+impl Action {
+    /// Picks the concrete action a random one stands for this time round.
+    /// Other actions come back as they are; `None` when there is nothing to
+    /// pick from.
+    pub fn pick(&self, rng: &mut impl Rng) -> Option<Action> {
+        match self {
+            Action::RandomColor { from, brightness } => {
+                let [r, g, b] = match from {
+                    Colors::Palette(colors) if colors.is_empty() => return None,
+                    Colors::Palette(colors) => colors[rng.gen_range(0..colors.len())],
+                    Colors::Hues { min, max } => {
+                        // hue ranges that wrap go on past 360
+                        let max = if max < min { max + 360.0 } else { *max };
+                        hue_rgb(match max > *min {
+                            true => rng.gen_range(*min..max),
+                            false => *min,
+                        })
+                    }
+                };
+                let mut pilot = Pilot::new(Method::SetPilot);
+                pilot.set_rgb(r, g, b);
+                pilot.set_brightness(*brightness);
+                Some(Action::SetPilot(pilot))
+            }
+            Action::RandomSleep { min, max } => Some(Action::Sleep(match max > min {
+                true => rng.gen_range(*min..=*max),
+                false => *min,
+            })),
+            Action::RandomScene { scenes, brightness } => {
+                if scenes.is_empty() {
+                    return None;
+                }
+                let mut pilot = Pilot::new(Method::SetPilot);
+                pilot.set_scene(scenes[rng.gen_range(0..scenes.len())]);
+                pilot.set_brightness(*brightness);
+                Some(Action::SetPilot(pilot))
+            }
+            action => Some(action.clone()),
+        }
+    }
+}
+
+/// What a program does once it runs past its last action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EndBehavior {
+    /// Starts over.
+    #[default]
+    Loop,
+    /// Ends, leaving the bulbs as they are.
+    Stop,
+    /// Ends, setting the bulbs back to how they were before it started.
+    Restore,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "ProgramRepr")]
+pub struct Program {
+    pub actions: Vec<Action>,
+    pub end: EndBehavior,
+    /// Makes the random actions pick the same way on every run.
+    pub seed: Option<u64>,
+}
+
+/// Programs used to be a bare list of actions, which still load.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProgramRepr {
+    Actions(Vec<Action>),
+    Program {
+        actions: Vec<Action>,
+        #[serde(default)]
+        end: EndBehavior,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+}
+
+impl From<ProgramRepr> for Program {
+    fn from(repr: ProgramRepr) -> Program {
+        match repr {
+            ProgramRepr::Actions(actions) => Program::from(actions),
+            ProgramRepr::Program { actions, end, seed } => Program { actions, end, seed },
+        }
+    }
+}
+
+impl From<Vec<Action>> for Program {
+    fn from(actions: Vec<Action>) -> Program {
+        Program {
+            actions,
+            end: EndBehavior::default(),
+            seed: None,
+        }
+    }
+}
+
+/// One instruction of a compiled [`Program`].
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// A `Sleep`, `SetPilot`, `Fade` or one of the random actions.
+    Do(Action),
+    /// Starts counting the loop in `slot`, skipping to `end` when `count` is
+    /// zero.
+    Enter {
+        slot: usize,
+        count: u32,
+        end: usize,
+    },
+    /// Goes back to `start` until the loop in `slot` ran `count` times.
+    Again {
+        slot: usize,
+        count: u32,
+        start: usize,
+    },
+    Jump(usize),
+    Hold,
+    Stop,
+}
+
+/// A [`Program`] flattened into ops that jump instead of nest.
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub ops: Vec<Op>,
+    /// How many loop counters the ops use.
+    pub slots: usize,
+    pub end: EndBehavior,
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    UnknownLabel(String),
+    DuplicateLabel(String),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::UnknownLabel(label) => write!(f, "no label {}", label),
+            ProgramError::DuplicateLabel(label) => write!(f, "label {} is used twice", label),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+impl Program {
+    pub fn compile(&self) -> Result<Compiled, ProgramError> {
+        let mut compiler = Compiler::default();
+        compiler.block(&self.actions)?;
+
+        let mut ops = compiler.ops;
+        for (at, label) in compiler.gotos {
+            let target = compiler
+                .labels
+                .get(&label)
+                .ok_or(ProgramError::UnknownLabel(label))?;
+            ops[at] = Op::Jump(*target);
+        }
+        Ok(Compiled {
+            ops,
+            slots: compiler.slots,
+            end: self.end,
+            seed: self.seed,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Compiler {
+    ops: Vec<Op>,
+    slots: usize,
+    labels: HashMap<String, usize>,
+    /// Jumps to patch once every label is known.
+    gotos: Vec<(usize, String)>,
+}
+
+impl Compiler {
+    fn block(&mut self, actions: &[Action]) -> Result<(), ProgramError> {
+        for action in actions {
+            match action {
+                Action::Sleep(_)
+                | Action::SetPilot(_)
+                | Action::Fade { .. }
+                | Action::RandomColor { .. }
+                | Action::RandomSleep { .. }
+                | Action::RandomScene { .. } => self.ops.push(Op::Do(action.clone())),
+                Action::Repeat { count, body } => {
+                    let slot = self.slots;
+                    self.slots += 1;
+                    let enter = self.ops.len();
+                    self.ops.push(Op::Stop);
+                    self.block(body)?;
+                    self.ops.push(Op::Again {
+                        slot,
+                        count: *count,
+                        start: enter + 1,
+                    });
+                    self.ops[enter] = Op::Enter {
+                        slot,
+                        count: *count,
+                        end: self.ops.len(),
+                    };
+                }
+                Action::Block(body) => self.block(body)?,
+                Action::Label(label) => {
+                    if self.labels.insert(label.clone(), self.ops.len()).is_some() {
+                        return Err(ProgramError::DuplicateLabel(label.clone()));
+                    }
+                }
+                Action::Goto(label) => {
+                    self.gotos.push((self.ops.len(), label.clone()));
+                    self.ops.push(Op::Stop);
+                }
+                Action::Hold => self.ops.push(Op::Hold),
+                Action::Stop => self.ops.push(Op::Stop),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    /// The program cannot run as written.
+    Error,
+    /// The program runs, though likely not as meant.
+    Warning,
+}
+
+/// Something [`validate`] found wrong with a program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The index of the action, then of the action in each block inside it.
+    /// Empty when the issue is with the whole program.
+    pub at: Vec<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.at.is_empty() {
+            return write!(f, "{}: {}", severity, self.message);
+        }
+        let at: Vec<String> = self.at.iter().map(|idx| (idx + 1).to_string()).collect();
+        write!(f, "{} at step {}: {}", severity, at.join("."), self.message)
+    }
+}
+
+/// Checks `program` before it runs on bulbs with `caps`: labels that do not
+/// match, loops that never wait, values out of range, settings that do not
+/// go together, and ones the bulbs cannot do.
+pub fn validate(program: &Program, caps: &[Capabilities]) -> Vec<Issue> {
+    let mut validator = Validator {
+        caps,
+        at: Vec::new(),
+        issues: Vec::new(),
+    };
+    match program.compile() {
+        Ok(compiled) if spins(&compiled) => validator.program_error(String::from(
+            "loops forever without waiting, flooding the bulbs",
+        )),
+        Ok(_) => {}
+        Err(e) => validator.program_error(e.to_string()),
+    }
+    validator.block(&program.actions);
+    validator.issues
+}
+
+/// The least time `actions` take, going straight through.
+fn min_duration(actions: &[Action]) -> Duration {
+    actions
+        .iter()
+        .map(|action| match action {
+            Action::Sleep(duration) => *duration,
+            Action::Fade { duration_ms, .. } => Duration::from_millis(*duration_ms),
+            Action::RandomSleep { min, max } => *min.min(max),
+            Action::Repeat { count, body } => min_duration(body) * *count,
+            Action::Block(body) => min_duration(body),
+            _ => Duration::ZERO,
+        })
+        .sum()
+}
+
+/// Whether the ops can go round forever without any time passing. Repeats
+/// end on their own, so only jumps and looping back to the start count.
+fn spins(compiled: &Compiled) -> bool {
+    let ops = &compiled.ops;
+    let len = ops.len();
+    let waits = |idx: usize| match ops.get(idx) {
+        Some(Op::Do(action)) => min_duration(std::slice::from_ref(action)) > Duration::ZERO,
+        _ => false,
+    };
+    // past the last op, at `len`, a looping program goes back to the start
+    let next = |idx: usize| -> Vec<usize> {
+        match ops.get(idx) {
+            None if compiled.end == EndBehavior::Loop && len > 0 => vec![0],
+            None | Some(Op::Hold | Op::Stop) => Vec::new(),
+            Some(Op::Do(_) | Op::Again { .. }) => vec![idx + 1],
+            Some(Op::Enter { end, .. }) => vec![idx + 1, *end],
+            Some(Op::Jump(target)) => vec![*target],
+        }
+    };
+
+    // depth-first search for a cycle of ops that all take no time
+    let mut state = vec![0u8; len + 1];
+    for start in 0..=len {
+        if state[start] != 0 || waits(start) {
+            continue;
+        }
+        let mut stack = vec![(start, next(start), 0)];
+        state[start] = 1;
+        while let Some((idx, successors, at)) = stack.last_mut() {
+            match successors.get(*at).copied() {
+                Some(succ) => {
+                    *at += 1;
+                    if waits(succ) {
+                        continue;
+                    }
+                    match state[succ] {
+                        0 => {
+                            state[succ] = 1;
+                            stack.push((succ, next(succ), 0));
+                        }
+                        1 => return true,
+                        _ => {}
+                    }
+                }
+                None => {
+                    state[*idx] = 2;
+                    stack.pop();
+                }
+            }
+        }
+    }
+    false
+}
+
+struct Validator<'a> {
+    caps: &'a [Capabilities],
+    at: Vec<usize>,
+    issues: Vec<Issue>,
+}
+
+impl Validator<'_> {
+    fn issue(&mut self, severity: Severity, message: String) {
+        self.issues.push(Issue {
+            severity,
+            at: self.at.clone(),
+            message,
+        });
+    }
+
+    fn program_error(&mut self, message: String) {
+        self.issue(Severity::Error, message);
+    }
+
+    fn brightness(&mut self, brightness: f32) {
+        if !(MIN_BRIGHTNESS..=1.0).contains(&brightness) {
+            self.issue(
+                Severity::Error,
+                format!("brightness {} is outside 0.1 to 1.0", brightness),
+            );
+        }
+    }
+
+    fn pilot(&mut self, pilot: &Pilot) {
+        if let Err(e) = pilot.validate() {
+            return self.issue(Severity::Error, e.to_string());
+        }
+        if pilot
+            .rgb
+            .is_some_and(|rgb| rgb.iter().any(|c| !(0.0..=1.0).contains(c)))
+        {
+            return self.issue(Severity::Error, String::from("rgb is out of range"));
+        }
+        // the same complaint from each bulb is reported once
+        let mut errors: Vec<String> = Vec::new();
+        for caps in self.caps {
+            if let Err(e) = caps.check(pilot) {
+                let e = e.to_string();
+                if !errors.contains(&e) {
+                    errors.push(e);
+                }
+            }
+        }
+        for e in errors {
+            self.issue(Severity::Error, e);
+        }
+    }
+
+    fn block(&mut self, actions: &[Action]) {
+        for (idx, action) in actions.iter().enumerate() {
+            self.at.push(idx);
+            self.action(action);
+            self.at.pop();
+        }
+    }
+
+    fn action(&mut self, action: &Action) {
+        match action {
+            Action::SetPilot(pilot) | Action::Fade { to: pilot, .. } => self.pilot(pilot),
+            Action::RandomColor { from, brightness } => {
+                self.brightness(*brightness);
+                match from {
+                    Colors::Palette(colors) if colors.is_empty() => self.issue(
+                        Severity::Warning,
+                        String::from("has no colors to pick from"),
+                    ),
+                    Colors::Palette(_) => {}
+                    Colors::Hues { min, max } => {
+                        if ![min, max].iter().all(|hue| (0.0..=360.0).contains(*hue)) {
+                            self.issue(
+                                Severity::Error,
+                                String::from("hues are outside 0 to 360 degrees"),
+                            );
+                        }
+                    }
+                }
+                if self.caps.iter().any(|caps| !caps.supports_rgb) {
+                    self.issue(Severity::Error, PilotError::Unsupported("rgb").to_string());
+                }
+            }
+            Action::RandomSleep { min, max } => {
+                if min > max {
+                    self.issue(
+                        Severity::Warning,
+                        String::from("the shortest sleep is longer than the longest"),
+                    );
+                }
+            }
+            Action::RandomScene { scenes, brightness } => {
+                self.brightness(*brightness);
+                if scenes.is_empty() {
+                    self.issue(
+                        Severity::Warning,
+                        String::from("has no scenes to pick from"),
+                    );
+                }
+                for scene in scenes {
+                    if self.caps.iter().any(|caps| !caps.supports_scene(*scene)) {
+                        self.issue(
+                            Severity::Warning,
+                            format!("{} is not supported by every bulb", scene),
+                        );
+                    }
+                }
+            }
+            Action::Repeat { count, body } => {
+                if *count == 0 {
+                    self.issue(Severity::Warning, String::from("repeats zero times"));
+                } else if *count > 1 && !body.is_empty() && min_duration(body) == Duration::ZERO {
+                    self.issue(
+                        Severity::Warning,
+                        String::from("repeats without waiting, flooding the bulbs"),
+                    );
+                }
+                self.block(body);
+            }
+            Action::Block(body) => self.block(body),
+            Action::Sleep(_) | Action::Label(_) | Action::Goto(_) | Action::Hold | Action::Stop => {
+            }
+        }
+    }
+}
+
+/// Simulations give up on ops that go round this often without any time
+/// passing.
+const MAX_SPIN: usize = 10_000;
+
+/// What a program does to the bulbs, as worked out by [`simulate`].
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// Each state the bulbs are set to, and when from the start. Fades come
+    /// a frame at a time.
+    pub points: Vec<(Duration, Pilot)>,
+    /// How long the program runs, or the limit if it runs on past it.
+    pub duration: Duration,
+    /// Whether the program still runs at the limit, because it loops,
+    /// holds or goes round without waiting.
+    pub cut: bool,
+}
+
+impl Timeline {
+    /// The state at `time`; `None` before the program sets one.
+    pub fn at(&self, time: Duration) -> Option<&Pilot> {
+        let idx = self.points.partition_point(|(at, _)| *at <= time);
+        idx.checked_sub(1).map(|idx| &self.points[idx].1)
+    }
+}
+
+/// Runs `program` against a virtual clock for up to `limit`, sampling fades
+/// every `frame` as the daemon sends them. Random actions pick as seeded, or
+/// with seed 0 when the program has none. Bulbs start in an unknown state,
+/// so a fade before anything was set jumps straight to its end.
+pub fn simulate(
+    program: &Program,
+    limit: Duration,
+    frame: Duration,
+) -> Result<Timeline, ProgramError> {
+    let compiled = program.compile()?;
+    let ops = &compiled.ops;
+    let mut rng = StdRng::seed_from_u64(program.seed.unwrap_or_default());
+    let mut counters = vec![0; compiled.slots];
+    let mut timeline = Timeline::default();
+    let mut last: Option<Pilot> = None;
+    let mut now = Duration::ZERO;
+    let mut idx = 0;
+    // ops run since time last passed
+    let mut spin = 0;
+
+    loop {
+        if now >= limit || spin > MAX_SPIN {
+            timeline.cut = true;
+            break;
+        }
+        if idx >= ops.len() {
+            match program.end {
+                EndBehavior::Loop if !ops.is_empty() => idx = 0,
+                // where restored bulbs go back to is not known offline
+                _ => break,
+            }
+        }
+        spin += 1;
+
+        let action = match ops[idx] {
+            Op::Do(ref action) => action.pick(&mut rng),
+            Op::Enter {
+                slot,
+                count,
+                end: past,
+            } => {
+                counters[slot] = 0;
+                idx = if count == 0 { past } else { idx + 1 };
+                continue;
+            }
+            Op::Again { slot, count, start } => {
+                counters[slot] += 1;
+                idx = if counters[slot] < count {
+                    start
+                } else {
+                    idx + 1
+                };
+                continue;
+            }
+            Op::Jump(target) => {
+                idx = target;
+                continue;
+            }
+            Op::Hold => {
+                timeline.cut = true;
+                break;
+            }
+            Op::Stop => break,
+        };
+        idx += 1;
+
+        let before = now;
+        match action {
+            Some(Action::Sleep(duration)) => now += duration,
+            Some(Action::SetPilot(pilot)) => {
+                timeline.points.push((now, pilot.clone()));
+                last = Some(pilot);
+            }
+            Some(Action::Fade {
+                to,
+                duration_ms,
+                easing,
+            }) => {
+                let duration = Duration::from_millis(duration_ms);
+                let from = last.unwrap_or_else(|| to.clone());
+                let mut elapsed = Duration::ZERO;
+                while elapsed < duration && !frame.is_zero() {
+                    let t = elapsed.as_secs_f32() / duration.as_secs_f32();
+                    let pilot = interpolate(&from, &to, t, easing);
+                    timeline.points.push((now + elapsed, pilot));
+                    elapsed += frame;
+                }
+                now += duration;
+                timeline.points.push((now, to.clone()));
+                last = Some(to);
+            }
+            // random picks with nothing to pick from
+            _ => {}
+        }
+        if now > before {
+            spin = 0;
+        }
+    }
+
+    timeline.duration = match timeline.cut {
+        true => limit,
+        false => now.min(limit),
+    };
+    Ok(timeline)
+}
+
+/// Sleeps are saved in milliseconds. Programs saved before that have whole
+/// seconds, which still load.
+mod sleep {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Secs(u64),
+        Millis { ms: u64 },
+    }
+
+    pub fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        Repr::Millis {
+            ms: duration.as_millis() as u64,
+        }
+        .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        Ok(match Repr::deserialize(d)? {
+            Repr::Secs(secs) => Duration::from_secs(secs),
+            Repr::Millis { ms } => Duration::from_millis(ms),
+        })
+    }
+}
+
+/// How a [`Action::Fade`] progresses over its duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts and ends slowly.
+    EaseInOut,
+    /// Even steps in perceived lightness (CIE L*) rather than in dimming, so
+    /// the low end does not rush by.
+    Perceptual,
+}
+
+impl Easing {
+    /// Eases `t`, the share of the duration that passed, from 0 to 1.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear | Easing::Perceptual => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// CIE lightness, 0 to 100, of relative luminance `y`.
+fn lightness(y: f32) -> f32 {
+    if y > 216.0 / 24389.0 {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        y * 24389.0 / 27.0
+    }
+}
+
+/// Relative luminance of CIE lightness `l`, the inverse of [`lightness`].
+fn luminance(l: f32) -> f32 {
+    if l > 8.0 {
+        ((l + 16.0) / 116.0).powi(3)
+    } else {
+        l * 27.0 / 24389.0
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+/// The pilot a fade from `from` to `to` sends once `t` of its duration passed.
+pub fn interpolate(from: &Pilot, to: &Pilot, t: f32, easing: Easing) -> Pilot {
+    if t >= 1.0 {
+        return to.clone();
+    }
+    let eased = easing.apply(t);
+
+    let brightness = |pilot: &Pilot| match pilot.state {
+        true => pilot.brightness,
+        false => MIN_BRIGHTNESS,
+    };
+    let (start, end) = (brightness(from), brightness(to));
+    let brightness = match easing {
+        Easing::Perceptual => luminance(lerp(lightness(start), lightness(end), eased)),
+        Easing::Linear | Easing::EaseInOut => lerp(start, end, eased),
+    };
+
+    let mut pilot = to.clone();
+    pilot.scene = None;
+    // on until the very end of a fade out
+    pilot.state = from.state || to.state;
+    pilot.brightness = brightness.clamp(MIN_BRIGHTNESS, 1.0);
+    if let (Some(from), Some(to)) = (from.rgb, to.rgb) {
+        pilot.rgb = Some([0, 1, 2].map(|i| lerp(from[i], to[i], eased)));
+    }
+    if let (Some(from), Some(to)) = (from.temp, to.temp) {
+        pilot.temp = Some(lerp(from as f32, to as f32, eased).round() as u32);
+    }
+    if let (Some(from), Some(to)) = (from.cold, to.cold) {
+        pilot.cold = Some(lerp(from as f32, to as f32, eased).round() as u8);
+    }
+    if let (Some(from), Some(to)) = (from.warm, to.warm) {
+        pilot.warm = Some(lerp(from as f32, to as f32, eased).round() as u8);
+    }
+    pilot
 }
@@ -0,0 +1,173 @@
+use serde_json::json;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bulb::Bulb;
+use crate::discovery::local_address_for;
+use crate::error::WizardError;
+use crate::pilot::PilotState;
+use crate::wizard::bulb_addr;
+
+/// Port the bulbs push `syncPilot` messages to.
+pub const PUSH_PORT: u16 = 38900;
+
+/// Bulbs forget a registration after a while, so it is renewed this often.
+const REGISTRATION_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Identifies us to the bulb; it only has to look like a MAC.
+const PHONE_MAC: &str = "AAAAAAAAAAAA";
+
+/// A state change pushed by a bulb, e.g. from a wall switch or the app.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub mac: String,
+    pub ip: String,
+    pub state: PilotState,
+}
+
+struct Registration {
+    mac: String,
+    ip: String,
+}
+
+/// Listens for `syncPilot` pushes from the bulbs registered with it.
+pub struct PushListener {
+    socket: Arc<Socket>,
+    registrations: Arc<Mutex<Vec<Registration>>>,
+    subscribers: Arc<Mutex<Vec<Sender<StateChange>>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl PushListener {
+    /// Binds [`PUSH_PORT`] and starts the listener thread.
+    ///
+    /// Only one process can listen at a time, a second one gets
+    /// [`WizardError::Bind`].
+    pub fn start() -> Result<PushListener, WizardError> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], PUSH_PORT));
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        socket
+            .bind(&addr.into())
+            .map_err(|e| WizardError::Bind(PUSH_PORT, e))?;
+
+        let listener = PushListener {
+            socket: Arc::new(socket),
+            registrations: Arc::new(Mutex::new(Vec::new())),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            running: Arc::new(AtomicBool::new(true)),
+        };
+
+        let socket = listener.socket.clone();
+        let registrations = listener.registrations.clone();
+        let subscribers = listener.subscribers.clone();
+        let running = listener.running.clone();
+        thread::spawn(move || {
+            let mut next_renewal = Instant::now() + REGISTRATION_INTERVAL;
+            let mut buf = [MaybeUninit::new(0u8); 1024];
+            while running.load(Ordering::SeqCst) {
+                if Instant::now() >= next_renewal {
+                    for registration in registrations.lock().unwrap().iter() {
+                        let _ = send_registration(&socket, &registration.ip);
+                    }
+                    next_renewal = Instant::now() + REGISTRATION_INTERVAL;
+                }
+
+                let Ok((amt, src)) = socket.recv_from(&mut buf) else {
+                    continue;
+                };
+                let Some(src) = src.as_socket_ipv4() else {
+                    continue;
+                };
+                let pbuf: Vec<u8> = buf[..amt]
+                    .iter()
+                    .map(|c| unsafe { c.assume_init() })
+                    .collect();
+                let data = String::from_utf8_lossy(&pbuf);
+                let Some((mac, state)) = PilotState::parse_sync(&data) else {
+                    continue;
+                };
+
+                let ip = src.ip().to_string();
+                for registration in registrations.lock().unwrap().iter_mut() {
+                    // keep renewing at the bulb's current address
+                    if registration.mac == mac {
+                        registration.ip = ip.clone();
+                    }
+                }
+
+                let change = StateChange { mac, ip, state };
+                subscribers
+                    .lock()
+                    .unwrap()
+                    .retain(|tx| tx.send(change.clone()).is_ok());
+            }
+        });
+
+        Ok(listener)
+    }
+
+    /// Asks the bulb to push its state changes to us.
+    pub fn register(&self, bulb: &Bulb) -> Result<(), WizardError> {
+        send_registration(&self.socket, &bulb.ip)?;
+
+        let mut registrations = self.registrations.lock().unwrap();
+        registrations.retain(|r| r.mac != bulb.mac);
+        registrations.push(Registration {
+            mac: bulb.mac.clone(),
+            ip: bulb.ip.clone(),
+        });
+        Ok(())
+    }
+
+    /// Stops renewing the registration; the bulb stops pushing soon after.
+    pub fn unregister(&self, mac: &str) {
+        self.registrations.lock().unwrap().retain(|r| r.mac != mac);
+    }
+
+    /// Receives every state change from now on.
+    pub fn subscribe(&self) -> Receiver<StateChange> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for PushListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn send_registration(socket: &Socket, ip: &str) -> Result<(), WizardError> {
+    let addr = bulb_addr(ip)?;
+    let bulb_ip: Ipv4Addr = ip
+        .parse()
+        .map_err(|_| WizardError::InvalidAddress(ip.to_string()))?;
+    let phone_ip = local_address_for(bulb_ip)
+        .ok_or_else(|| WizardError::NoLocalIp(String::from("no IPv4 interface is up")))?;
+
+    let msg = json!({
+        "method": "registration",
+        "params": {
+            "phoneMac": PHONE_MAC,
+            "register": true,
+            "phoneIp": phone_ip.to_string(),
+            "id": "1",
+        },
+    });
+    socket.send_to(msg.to_string().as_bytes(), &addr.into())?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use serde_json::Value;
+use socket2::Socket;
+
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::pilot::Method;
+
+/// A datagram received from a bulb.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub ip: String,
+    pub method: String,
+    pub data: String,
+}
+
+impl Reply {
+    pub fn parse(ip: String, data: &str) -> Option<Reply> {
+        let data = data.trim_matches(char::from(0));
+        let v: Value = serde_json::from_str(data).ok()?;
+        let method = v["method"].as_str()?.to_string();
+        Some(Reply {
+            ip,
+            method,
+            data: data.to_string(),
+        })
+    }
+
+    /// Interprets the reply as a `setPilot` acknowledgement.
+    ///
+    /// Returns the bulb's error message when it refused the request.
+    pub fn ack(&self) -> Result<(), String> {
+        let v: Value = serde_json::from_str(&self.data).map_err(|e| e.to_string())?;
+        if let Some(error) = v.get("error") {
+            let message = error["message"].as_str().unwrap_or("unknown error");
+            return Err(message.to_string());
+        }
+        if v["result"]["success"] == true {
+            Ok(())
+        } else {
+            Err(String::from("bulb did not report success"))
+        }
+    }
+}
+
+struct Waiter {
+    id: u64,
+    ip: Option<String>,
+    methods: Vec<String>,
+    tx: Box<dyn Fn(Reply) + Send>,
+}
+
+/// Routes replies read from the shared socket to whoever is waiting for them.
+///
+/// Every receive on the Wizard socket happens on the router thread, so a
+/// `getPilot` or `setPilot` caller and a running discovery never steal each
+/// other's datagrams. Waiters are matched on the source ip and the method.
+#[derive(Clone)]
+pub struct Replies {
+    waiters: Arc<Mutex<Vec<Waiter>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Replies {
+    pub(crate) fn new() -> Replies {
+        Replies {
+            waiters: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn spawn(socket: Arc<Socket>, running: Arc<AtomicBool>) -> Replies {
+        let replies = Replies::new();
+
+        let router = replies.clone();
+        thread::spawn(move || {
+            let mut buf = [MaybeUninit::new(0u8); 1024];
+            while running.load(Ordering::SeqCst) {
+                // the read timeout wakes us up to check `running`
+                let Ok((amt, src)) = socket.recv_from(&mut buf) else {
+                    continue;
+                };
+                let Some(src) = src.as_socket_ipv4() else {
+                    continue;
+                };
+                let pbuf: Vec<u8> = buf[..amt]
+                    .iter()
+                    .map(|c| unsafe { c.assume_init() })
+                    .collect();
+                let data = String::from_utf8_lossy(&pbuf);
+                if let Some(reply) = Reply::parse(src.ip().to_string(), &data) {
+                    router.dispatch(reply);
+                }
+            }
+        });
+
+        replies
+    }
+
+    fn register<R>(
+        &self,
+        ip: Option<String>,
+        methods: &[Method],
+        tx: Box<dyn Fn(Reply) + Send>,
+        rx: R,
+    ) -> Subscription<R> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.waiters.lock().unwrap().push(Waiter {
+            id,
+            ip,
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+            tx,
+        });
+        Subscription {
+            id,
+            rx,
+            waiters: self.waiters.clone(),
+        }
+    }
+
+    /// Starts collecting replies to `method`, from `ip` or from any bulb.
+    pub fn subscribe(&self, ip: Option<String>, method: Method) -> Subscription {
+        self.subscribe_methods(ip, &[method])
+    }
+
+    /// Like [`Replies::subscribe`] for several methods on one channel.
+    pub fn subscribe_methods(&self, ip: Option<String>, methods: &[Method]) -> Subscription {
+        let (tx, rx): (Sender<Reply>, Receiver<Reply>) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        self.register(
+            ip,
+            methods,
+            Box::new(move |reply| {
+                let _ = tx.lock().unwrap().send(reply);
+            }),
+            rx,
+        )
+    }
+
+    /// Like [`Replies::subscribe`] but for use from an async task.
+    #[cfg(feature = "async")]
+    pub fn subscribe_async(
+        &self,
+        ip: Option<String>,
+        methods: &[Method],
+    ) -> Subscription<tokio::sync::mpsc::UnboundedReceiver<Reply>> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        self.register(
+            ip,
+            methods,
+            Box::new(move |reply| {
+                let _ = tx.send(reply);
+            }),
+            rx,
+        )
+    }
+
+    pub(crate) fn dispatch(&self, reply: Reply) {
+        let waiters = self.waiters.lock().unwrap();
+        for waiter in waiters.iter() {
+            let ip_matches = waiter.ip.as_ref().is_none_or(|ip| *ip == reply.ip);
+            if ip_matches && waiter.methods.contains(&reply.method) {
+                (waiter.tx)(reply.clone());
+            }
+        }
+    }
+}
+
+/// Receiving end of [`Replies::subscribe`]; unsubscribes when dropped.
+pub struct Subscription<R = Receiver<Reply>> {
+    id: u64,
+    pub rx: R,
+    waiters: Arc<Mutex<Vec<Waiter>>>,
+}
+
+impl<R> Drop for Subscription<R> {
+    fn drop(&mut self) {
+        self.waiters.lock().unwrap().retain(|w| w.id != self.id);
+    }
+}
@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-#[derive(Debug, Clone, Copy, EnumIter, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
 pub enum Scene {
     Ocean = 1,
     Romance,
@@ -37,42 +39,49 @@ pub enum Scene {
     Steampunk,
 }
 
-// to string
 impl Scene {
-    pub fn to_string(&self) -> String {
-        match self {
-            Scene::Ocean => String::from("Ocean"),
-            Scene::Romance => String::from("Romance"),
-            Scene::Sunset => String::from("Sunset"),
-            Scene::Party => String::from("Party"),
-            Scene::Fireplace => String::from("Fireplace"),
-            Scene::Cozy => String::from("Cozy"),
-            Scene::Forest => String::from("Forest"),
-            Scene::PastelColors => String::from("Pastel Colors"),
-            Scene::Wakeup => String::from("Wake Up"),
-            Scene::Bedtime => String::from("Bedtime"),
-            Scene::WarmWhite => String::from("Warm White"),
-            Scene::Daylight => String::from("Daylight"),
-            Scene::Coolwhite => String::from("Cool White"),
-            Scene::Nightlight => String::from("Night Light"),
-            Scene::Focus => String::from("Focus"),
-            Scene::Relax => String::from("Relax"),
-            Scene::Truecolors => String::from("True Colors"),
-            Scene::TVtime => String::from("TV Time"),
-            Scene::Plantgrowth => String::from("Plant Growth"),
-            Scene::Spring => String::from("Spring"),
-            Scene::Summer => String::from("Summer"),
-            Scene::Fall => String::from("Fall"),
-            Scene::Deepdive => String::from("Deep Dive"),
-            Scene::Jungle => String::from("Jungle"),
-            Scene::Mojito => String::from("Mojito"),
-            Scene::Club => String::from("Club"),
-            Scene::Christmas => String::from("Christmas"),
-            Scene::Halloween => String::from("Halloween"),
-            Scene::Candlelight => String::from("Candlelight"),
-            Scene::Goldenwhite => String::from("Golden White"),
-            Scene::Pulse => String::from("Pulse"),
-            Scene::Steampunk => String::from("Steampunk"),
-        }
+    /// Looks up a scene by the `sceneId` the bulb reports.
+    pub fn from_id(id: u8) -> Option<Scene> {
+        Scene::iter().find(|scene| *scene as u8 == id)
+    }
+}
+
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scene::Ocean => "Ocean",
+            Scene::Romance => "Romance",
+            Scene::Sunset => "Sunset",
+            Scene::Party => "Party",
+            Scene::Fireplace => "Fireplace",
+            Scene::Cozy => "Cozy",
+            Scene::Forest => "Forest",
+            Scene::PastelColors => "Pastel Colors",
+            Scene::Wakeup => "Wake Up",
+            Scene::Bedtime => "Bedtime",
+            Scene::WarmWhite => "Warm White",
+            Scene::Daylight => "Daylight",
+            Scene::Coolwhite => "Cool White",
+            Scene::Nightlight => "Night Light",
+            Scene::Focus => "Focus",
+            Scene::Relax => "Relax",
+            Scene::Truecolors => "True Colors",
+            Scene::TVtime => "TV Time",
+            Scene::Plantgrowth => "Plant Growth",
+            Scene::Spring => "Spring",
+            Scene::Summer => "Summer",
+            Scene::Fall => "Fall",
+            Scene::Deepdive => "Deep Dive",
+            Scene::Jungle => "Jungle",
+            Scene::Mojito => "Mojito",
+            Scene::Club => "Club",
+            Scene::Christmas => "Christmas",
+            Scene::Halloween => "Halloween",
+            Scene::Candlelight => "Candlelight",
+            Scene::Goldenwhite => "Golden White",
+            Scene::Pulse => "Pulse",
+            Scene::Steampunk => "Steampunk",
+        };
+        f.write_str(name)
     }
 }
@@ -0,0 +1,155 @@
+use chrono::{Datelike, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::Bulb;
+use crate::pilot::Pilot;
+use crate::program::Program;
+use crate::solar::{sun_event, Location, SunEvent};
+
+/// Identifies a schedule kept by the daemon.
+pub type ScheduleId = u64;
+
+/// Monday to Friday, for [`Schedule::days`].
+pub const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+/// How far ahead a schedule looks for its next start before giving up.
+const LOOKAHEAD_DAYS: u64 = 8;
+
+/// When, on a given day, a schedule fires. Times are local wall-clock time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+    At(NaiveTime),
+    /// `offset_mins` after the sun event, or before it when negative. Needs
+    /// the daemon's location.
+    Sun {
+        event: SunEvent,
+        offset_mins: i32,
+    },
+}
+
+impl Trigger {
+    /// The moment the trigger fires on `date`, if it does.
+    pub fn on(&self, date: NaiveDate, location: Option<&Location>) -> Option<NaiveDateTime> {
+        match self {
+            Trigger::At(time) => Some(date.and_time(*time)),
+            Trigger::Sun { event, offset_mins } => {
+                let time = sun_event(date, *event, location?)?;
+                let time = time + chrono::Duration::minutes(*offset_mins as i64);
+                Some(time.with_timezone(&Local).naive_local())
+            }
+        }
+    }
+}
+
+/// What a schedule does when it fires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Task {
+    /// Runs a program on the bulbs, replacing what ran on them before.
+    Program(Program),
+    /// Sets the bulbs once, stopping any program that drove them.
+    Pilot(Pilot),
+}
+
+/// Keeps a schedule's task running until `until`, then runs `then`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Window {
+    pub until: Trigger,
+    pub then: Task,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    /// Days the schedule starts on; every day when empty.
+    pub days: Vec<Weekday>,
+    pub at: Trigger,
+    pub bulbs: Vec<Bulb>,
+    pub task: Task,
+    /// Ends what `task` started, on the same or the next day.
+    #[serde(default)]
+    pub window: Option<Window>,
+}
+
+impl Schedule {
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        self.days.is_empty() || self.days.contains(&date.weekday())
+    }
+
+    /// The first start strictly after `after`.
+    pub fn next_start(
+        &self,
+        after: NaiveDateTime,
+        location: Option<&Location>,
+    ) -> Option<NaiveDateTime> {
+        (0..LOOKAHEAD_DAYS)
+            .filter_map(|days| after.date().checked_add_days(Days::new(days)))
+            .filter(|date| self.runs_on(*date))
+            .filter_map(|date| self.at.on(date, location))
+            .find(|start| *start > after)
+    }
+
+    /// The last start at or before `before`.
+    pub fn previous_start(
+        &self,
+        before: NaiveDateTime,
+        location: Option<&Location>,
+    ) -> Option<NaiveDateTime> {
+        (0..LOOKAHEAD_DAYS)
+            .filter_map(|days| before.date().checked_sub_days(Days::new(days)))
+            .filter(|date| self.runs_on(*date))
+            .filter_map(|date| self.at.on(date, location))
+            .find(|start| *start <= before)
+    }
+
+    /// When the window opened at `start` closes, if the schedule has one.
+    pub fn window_end(
+        &self,
+        start: NaiveDateTime,
+        location: Option<&Location>,
+    ) -> Option<NaiveDateTime> {
+        let window = self.window.as_ref()?;
+        // a window past midnight closes on the next day
+        (0..2)
+            .filter_map(|days| start.date().checked_add_days(Days::new(days)))
+            .filter_map(|date| window.until.on(date, location))
+            .find(|end| *end > start)
+    }
+
+    /// The next `count` starts after `after`, for previewing a schedule.
+    pub fn upcoming(
+        &self,
+        after: NaiveDateTime,
+        location: Option<&Location>,
+        count: usize,
+    ) -> Vec<NaiveDateTime> {
+        let mut starts = Vec::with_capacity(count);
+        let mut after = after;
+        while starts.len() < count {
+            match self.next_start(after, location) {
+                Some(start) => {
+                    starts.push(start);
+                    after = start;
+                }
+                None => break,
+            }
+        }
+        starts
+    }
+}
+
+/// A schedule as reported by the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleInfo {
+    pub id: ScheduleId,
+    pub schedule: Schedule,
+    /// When it next starts, in local time.
+    pub next: Option<NaiveDateTime>,
+    /// When its open window closes, if one is open.
+    pub window_end: Option<NaiveDateTime>,
+}
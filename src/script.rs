@@ -0,0 +1,567 @@
+//! A plain-text format for programs, for keeping them in files of their own
+//! and editing them by hand:
+//!
+//! ```text
+//! // wake up slowly, then party
+//! end restore
+//!
+//! off
+//! fade 30s perceptual to color #ff8800 dim 40
+//! repeat 3 {
+//!     color #ff0000 dim 80; wait 500ms
+//!     scene Ocean
+//!     wait 100ms to 2s
+//! }
+//! ```
+//!
+//! Statements end at a newline or `;`, and `//` starts a comment.
+
+use std::fmt::{self, Write};
+use std::time::Duration;
+
+use strum::IntoEnumIterator;
+
+use crate::pilot::Pilot;
+use crate::program::{Action, Colors, Easing, EndBehavior, Program};
+use crate::scenes::Scene;
+
+/// Why a program file did not parse, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Word(String),
+    /// A newline or `;`.
+    End,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line, source) in text.lines().enumerate() {
+        let source = source.split("//").next().unwrap_or_default();
+        let mut word: Option<(String, usize)> = None;
+        for (column, c) in source.chars().chain([' ']).enumerate() {
+            let kind = match c {
+                ';' => Some(Kind::End),
+                '{' => Some(Kind::Open),
+                '}' => Some(Kind::Close),
+                c if c.is_whitespace() => None,
+                c => {
+                    word.get_or_insert_with(|| (String::new(), column))
+                        .0
+                        .push(c);
+                    continue;
+                }
+            };
+            if let Some((text, start)) = word.take() {
+                tokens.push(Token {
+                    kind: Kind::Word(text),
+                    line: line + 1,
+                    column: start + 1,
+                });
+            }
+            if let Some(kind) = kind {
+                tokens.push(Token {
+                    kind,
+                    line: line + 1,
+                    column: column + 1,
+                });
+            }
+        }
+        tokens.push(Token {
+            kind: Kind::End,
+            line: line + 1,
+            column: source.chars().count() + 1,
+        });
+    }
+    tokens
+}
+
+/// Words that start or continue a light setting.
+const LOOKS: [&str; 9] = [
+    "on", "off", "color", "scene", "temp", "cold", "warm", "dim", "speed",
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    end: Option<EndBehavior>,
+    seed: Option<u64>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek().map(|token| &token.kind) {
+            Some(Kind::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn error(&self, message: String) -> ScriptError {
+        let (line, column) = match self.peek().or(self.tokens.last()) {
+            Some(token) => (token.line, token.column),
+            None => (1, 1),
+        };
+        ScriptError {
+            line,
+            column,
+            message,
+        }
+    }
+
+    /// An error at the word just taken.
+    fn error_back(&mut self, message: String) -> ScriptError {
+        self.pos -= 1;
+        self.error(message)
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, ScriptError> {
+        match self.peek().map(|token| token.kind.clone()) {
+            Some(Kind::Word(word)) => {
+                self.pos += 1;
+                Ok(word)
+            }
+            Some(Kind::End) | None => {
+                Err(self.error(format!("expected {}, found the end of the statement", what)))
+            }
+            Some(_) => Err(self.error(format!("expected {}", what))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ScriptError> {
+        match self.word(&format!("`{}`", keyword))? {
+            word if word == keyword => Ok(()),
+            word => Err(self.error_back(format!("expected `{}`, found `{}`", keyword, word))),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, ScriptError> {
+        let word = self.word(what)?;
+        word.parse()
+            .map_err(|_| self.error_back(format!("expected {}, found `{}`", what, word)))
+    }
+
+    fn duration(&mut self) -> Result<Duration, ScriptError> {
+        let word = self.word("a duration")?;
+        parse_duration(&word).ok_or_else(|| {
+            self.error_back(format!(
+                "expected a duration like 500ms or 2s, found `{}`",
+                word
+            ))
+        })
+    }
+
+    fn color(&mut self) -> Result<[u8; 3], ScriptError> {
+        let word = self.word("a color")?;
+        parse_color(&word).ok_or_else(|| {
+            self.error_back(format!("expected a color like #ff8800, found `{}`", word))
+        })
+    }
+
+    fn scene(&mut self) -> Result<Scene, ScriptError> {
+        let word = self.word("a scene")?;
+        Scene::iter()
+            .find(|scene| format!("{:?}", scene).eq_ignore_ascii_case(&word))
+            .ok_or_else(|| self.error_back(format!("unknown scene `{}`", word)))
+    }
+
+    fn brightness(&mut self) -> Result<f32, ScriptError> {
+        if self.peek_word() != Some("dim") {
+            return Ok(1.0);
+        }
+        self.pos += 1;
+        Ok(self.number::<u8>("a dimming percentage")? as f32 / 100.0)
+    }
+
+    /// A light setting such as `color #ff0000 dim 80`.
+    fn look(&mut self) -> Result<Pilot, ScriptError> {
+        let mut pilot = Pilot::default();
+        let start = self.pos;
+        while let Some(word) = self.peek_word().filter(|word| LOOKS.contains(word)) {
+            let word = word.to_string();
+            self.pos += 1;
+            match word.as_str() {
+                "on" => pilot.set_state(true),
+                "off" => pilot.set_state(false),
+                "color" => {
+                    let [r, g, b] = self.color()?;
+                    pilot.set_rgb(r, g, b);
+                }
+                "scene" => pilot.set_scene(self.scene()?),
+                "temp" => pilot.set_temp(self.number("a temperature in kelvin")?),
+                "cold" => pilot.set_cold(self.number("a cold white level")?),
+                "warm" => pilot.set_warm(self.number("a warm white level")?),
+                "dim" => {
+                    pilot.set_brightness(self.number::<u8>("a dimming percentage")? as f32 / 100.0)
+                }
+                _ => pilot.set_speed(self.number::<u8>("a speed percentage")? as f32 / 100.0),
+            }
+        }
+        if self.pos == start {
+            return Err(self.error(String::from("expected a light setting like color #ff0000")));
+        }
+        Ok(pilot)
+    }
+
+    /// Statements up to the closing brace, or the end of the file at the
+    /// top level.
+    fn block(&mut self, nested: bool) -> Result<Vec<Action>, ScriptError> {
+        let mut actions = Vec::new();
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                Some(Kind::End) => self.pos += 1,
+                Some(Kind::Close) if nested => {
+                    self.pos += 1;
+                    return Ok(actions);
+                }
+                Some(Kind::Close) => return Err(self.error(String::from("unmatched `}`"))),
+                None if nested => return Err(self.error(String::from("missing `}`"))),
+                None => return Ok(actions),
+                Some(_) => {
+                    actions.extend(self.statement(nested)?);
+                    match self.peek().map(|token| &token.kind) {
+                        Some(Kind::End | Kind::Close) | None => {}
+                        Some(Kind::Word(word)) => {
+                            return Err(self.error(format!(
+                                "expected the end of the statement, found `{}`",
+                                word
+                            )))
+                        }
+                        Some(Kind::Open) => return Err(self.error(String::from("unexpected `{`"))),
+                    }
+                }
+            }
+        }
+    }
+
+    fn body(&mut self) -> Result<Vec<Action>, ScriptError> {
+        match self.peek().map(|token| &token.kind) {
+            Some(Kind::Open) => {
+                self.pos += 1;
+                self.block(true)
+            }
+            _ => Err(self.error(String::from("expected `{`"))),
+        }
+    }
+
+    fn statement(&mut self, nested: bool) -> Result<Option<Action>, ScriptError> {
+        if self.peek_word().is_some_and(|word| LOOKS.contains(&word)) {
+            return Ok(Some(Action::SetPilot(self.look()?)));
+        }
+        let action = match self.word("a statement")?.as_str() {
+            "wait" => {
+                let min = self.duration()?;
+                match self.peek_word() {
+                    Some("to") => {
+                        self.pos += 1;
+                        let max = self.duration()?;
+                        Action::RandomSleep { min, max }
+                    }
+                    _ => Action::Sleep(min),
+                }
+            }
+            "fade" => {
+                let duration = self.duration()?;
+                let easing = match self.peek_word() {
+                    Some("to") => Easing::default(),
+                    _ => match self.word("an easing")?.as_str() {
+                        "linear" => Easing::Linear,
+                        "ease-in-out" => Easing::EaseInOut,
+                        "perceptual" => Easing::Perceptual,
+                        word => {
+                            let message = format!(
+                                "unknown easing `{}`, expected linear, ease-in-out or perceptual",
+                                word
+                            );
+                            return Err(self.error_back(message));
+                        }
+                    },
+                };
+                self.keyword("to")?;
+                Action::Fade {
+                    to: self.look()?,
+                    duration_ms: duration.as_millis() as u64,
+                    easing,
+                }
+            }
+            "random" => match self.word("color, hues or scene")?.as_str() {
+                "color" => {
+                    let mut colors = vec![self.color()?];
+                    while self.peek_word().is_some_and(|word| word.starts_with('#')) {
+                        colors.push(self.color()?);
+                    }
+                    Action::RandomColor {
+                        from: Colors::Palette(colors),
+                        brightness: self.brightness()?,
+                    }
+                }
+                "hues" => Action::RandomColor {
+                    from: Colors::Hues {
+                        min: self.number("a hue in degrees")?,
+                        max: self.number("a hue in degrees")?,
+                    },
+                    brightness: self.brightness()?,
+                },
+                "scene" => {
+                    let mut scenes = vec![self.scene()?];
+                    while self.peek_word().is_some_and(|word| word != "dim") {
+                        scenes.push(self.scene()?);
+                    }
+                    Action::RandomScene {
+                        scenes,
+                        brightness: self.brightness()?,
+                    }
+                }
+                word => {
+                    return Err(
+                        self.error_back(format!("expected color, hues or scene, found `{}`", word))
+                    )
+                }
+            },
+            "repeat" => {
+                let count = self.number("a count")?;
+                Action::Repeat {
+                    count,
+                    body: self.body()?,
+                }
+            }
+            "block" => Action::Block(self.body()?),
+            "label" => Action::Label(self.word("a label")?),
+            "goto" => Action::Goto(self.word("a label")?),
+            "hold" => Action::Hold,
+            "stop" => Action::Stop,
+            "end" | "seed" if nested => {
+                return Err(self.error_back(String::from("only allowed outside of blocks")))
+            }
+            "end" => {
+                self.end = Some(match self.word("loop, stop or restore")?.as_str() {
+                    "loop" => EndBehavior::Loop,
+                    "stop" => EndBehavior::Stop,
+                    "restore" => EndBehavior::Restore,
+                    word => {
+                        return Err(self.error_back(format!(
+                            "expected loop, stop or restore, found `{}`",
+                            word
+                        )))
+                    }
+                });
+                return Ok(None);
+            }
+            "seed" => {
+                self.seed = Some(self.number("a seed")?);
+                return Ok(None);
+            }
+            word => return Err(self.error_back(format!("unknown statement `{}`", word))),
+        };
+        Ok(Some(action))
+    }
+}
+
+/// `500ms`, `2s`, `1.5s` or `5m`.
+fn parse_duration(word: &str) -> Option<Duration> {
+    let (number, unit) = word.split_at(word.find(|c: char| c.is_ascii_alphabetic())?);
+    let number: f64 = number.parse().ok().filter(|n: &f64| *n >= 0.0)?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    Some(Duration::from_millis((secs * 1000.0).round() as u64))
+}
+
+/// `#rrggbb`.
+fn parse_color(word: &str) -> Option<[u8; 3]> {
+    let hex = word.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |at: usize| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Reads a program written in the text format.
+pub fn parse(text: &str) -> Result<Program, ScriptError> {
+    let mut parser = Parser {
+        tokens: tokenize(text),
+        pos: 0,
+        end: None,
+        seed: None,
+    };
+    let actions = parser.block(false)?;
+    Ok(Program {
+        actions,
+        end: parser.end.unwrap_or_default(),
+        seed: parser.seed,
+    })
+}
+
+fn format_duration(duration: Duration) -> String {
+    let ms = duration.as_millis();
+    match ms % 1000 {
+        0 if ms > 0 => format!("{}s", ms / 1000),
+        _ => format!("{}ms", ms),
+    }
+}
+
+fn format_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn format_dim(brightness: f32) -> String {
+    match (brightness * 100.0).round() as u8 {
+        100 => String::new(),
+        dimming => format!(" dim {}", dimming),
+    }
+}
+
+fn format_look(pilot: &Pilot) -> String {
+    let mut words = Vec::new();
+    if !pilot.state {
+        words.push(String::from("off"));
+    }
+    if let Some(rgb) = pilot.rgb {
+        words.push(format!(
+            "color {}",
+            format_color(rgb.map(|c| (c * 255.0).round() as u8))
+        ));
+    }
+    if let Some(scene) = pilot.scene {
+        words.push(format!("scene {:?}", scene));
+    }
+    if let Some(temp) = pilot.temp {
+        words.push(format!("temp {}", temp));
+    }
+    if let Some(cold) = pilot.cold {
+        words.push(format!("cold {}", cold));
+    }
+    if let Some(warm) = pilot.warm {
+        words.push(format!("warm {}", warm));
+    }
+    if pilot.dimming() != 100 {
+        words.push(format!("dim {}", pilot.dimming()));
+    }
+    if pilot.speed_percent() != Pilot::default().speed_percent() {
+        words.push(format!("speed {}", pilot.speed_percent()));
+    }
+    if words.is_empty() {
+        words.push(String::from("on"));
+    }
+    words.join(" ")
+}
+
+fn print_actions(out: &mut String, actions: &[Action], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for action in actions {
+        let _ = match action {
+            Action::Sleep(duration) => {
+                writeln!(out, "{}wait {}", indent, format_duration(*duration))
+            }
+            Action::SetPilot(pilot) => writeln!(out, "{}{}", indent, format_look(pilot)),
+            Action::Fade {
+                to,
+                duration_ms,
+                easing,
+            } => {
+                let easing = match easing {
+                    Easing::Linear => "",
+                    Easing::EaseInOut => " ease-in-out",
+                    Easing::Perceptual => " perceptual",
+                };
+                writeln!(
+                    out,
+                    "{}fade {}{} to {}",
+                    indent,
+                    format_duration(Duration::from_millis(*duration_ms)),
+                    easing,
+                    format_look(to)
+                )
+            }
+            Action::RandomColor { from, brightness } => {
+                let from = match from {
+                    Colors::Palette(colors) => {
+                        let colors: Vec<String> = colors.iter().map(|c| format_color(*c)).collect();
+                        format!("color {}", colors.join(" "))
+                    }
+                    Colors::Hues { min, max } => format!("hues {} {}", min, max),
+                };
+                writeln!(out, "{}random {}{}", indent, from, format_dim(*brightness))
+            }
+            Action::RandomSleep { min, max } => writeln!(
+                out,
+                "{}wait {} to {}",
+                indent,
+                format_duration(*min),
+                format_duration(*max)
+            ),
+            Action::RandomScene { scenes, brightness } => {
+                let scenes: Vec<String> = scenes.iter().map(|s| format!("{:?}", s)).collect();
+                writeln!(
+                    out,
+                    "{}random scene {}{}",
+                    indent,
+                    scenes.join(" "),
+                    format_dim(*brightness)
+                )
+            }
+            Action::Repeat { count, body } => {
+                let _ = writeln!(out, "{}repeat {} {{", indent, count);
+                print_actions(out, body, depth + 1);
+                writeln!(out, "{}}}", indent)
+            }
+            Action::Block(body) => {
+                let _ = writeln!(out, "{}block {{", indent);
+                print_actions(out, body, depth + 1);
+                writeln!(out, "{}}}", indent)
+            }
+            Action::Label(label) => writeln!(out, "{}label {}", indent, label),
+            Action::Goto(label) => writeln!(out, "{}goto {}", indent, label),
+            Action::Hold => writeln!(out, "{}hold", indent),
+            Action::Stop => writeln!(out, "{}stop", indent),
+        };
+    }
+}
+
+/// Writes `program` in the text format, one statement per line, so that
+/// [`parse`] reads it back.
+pub fn print(program: &Program) -> String {
+    let mut out = String::new();
+    match program.end {
+        EndBehavior::Loop => {}
+        EndBehavior::Stop => out.push_str("end stop\n"),
+        EndBehavior::Restore => out.push_str("end restore\n"),
+    }
+    if let Some(seed) = program.seed {
+        let _ = writeln!(out, "seed {}", seed);
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    print_actions(&mut out, &program.actions, 0);
+    out
+}
@@ -0,0 +1,314 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Map, Value};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::capabilities::Capabilities;
+use crate::error::WizardError;
+use crate::pilot::PilotState;
+use crate::scenes::Scene;
+use crate::wizard::WIZARD_PORT;
+
+/// A device for [`Simulator`] to emulate.
+#[derive(Debug, Clone)]
+pub struct SimBulb {
+    /// Address the bulb listens on, e.g. `127.0.0.2`. Every bulb needs its
+    /// own address since they all use [`WIZARD_PORT`].
+    pub ip: Ipv4Addr,
+    pub mac: String,
+    pub module_name: String,
+    pub fw_version: String,
+    pub rssi: i32,
+    pub state: PilotState,
+}
+
+impl SimBulb {
+    /// A full color bulb that is switched on at full warm white.
+    pub fn new(ip: Ipv4Addr) -> SimBulb {
+        let [_, b, c, d] = ip.octets();
+        SimBulb {
+            ip,
+            mac: format!("a8bb50{:02x}{:02x}{:02x}", b, c, d),
+            module_name: String::from("ESP03_SHRGB1C_01"),
+            fw_version: String::from("1.25.0"),
+            rssi: -62,
+            state: PilotState {
+                state: true,
+                dimming: Some(100),
+                temp: Some(2700),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn reply(&mut self, data: &str) -> Option<Value> {
+        let request: Value = serde_json::from_str(data.trim_matches(char::from(0))).ok()?;
+        let method = request["method"].as_str()?.to_string();
+
+        let result = match method.as_str() {
+            "getDevInfo" => Ok(json!({
+                "mac": self.mac,
+                "devMac": self.mac,
+                "moduleName": self.module_name,
+            })),
+            "getSystemConfig" => Ok(json!({
+                "mac": self.mac,
+                "homeId": 0,
+                "roomId": 0,
+                "rgn": "eu",
+                "moduleName": self.module_name,
+                "fwVersion": self.fw_version,
+                "groupId": 0,
+                "ping": 0,
+            })),
+            "getPilot" => {
+                let mut state = serde_json::to_value(&self.state).ok()?;
+                state["mac"] = json!(self.mac);
+                state["rssi"] = json!(self.rssi);
+                Ok(strip_nulls(state))
+            }
+            "setPilot" => self
+                .set_pilot(&request["params"])
+                .map(|_| json!({ "success": true })),
+            _ => Err((-32601, "Method not found")),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "method": method, "env": "pro", "result": result }),
+            Err((code, message)) => json!({
+                "method": method,
+                "env": "pro",
+                "error": { "code": code, "message": message },
+            }),
+        })
+    }
+
+    /// Applies `params` the way firmware does: a color, temperature or
+    /// scene replaces whichever of the others was active.
+    fn set_pilot(&mut self, params: &Value) -> Result<(), (i32, &'static str)> {
+        const INVALID: (i32, &str) = (-32602, "Invalid params");
+
+        let caps = Capabilities::from_module_name(&self.module_name);
+        let field = |name: &str| params[name].as_u64();
+        let byte = |name: &str| match field(name) {
+            Some(v) if v <= 255 => Ok(Some(v as u8)),
+            Some(_) => Err(INVALID),
+            None => Ok(None),
+        };
+
+        let mut next = self.state.clone();
+        if let Some(state) = params["state"].as_bool() {
+            next.state = state;
+        }
+        if let Some(dimming) = field("dimming") {
+            if !caps.supports_dimming || !(10..=100).contains(&dimming) {
+                return Err(INVALID);
+            }
+            next.dimming = Some(dimming as u8);
+        }
+        if let Some(speed) = field("speed") {
+            if !(10..=200).contains(&speed) {
+                return Err(INVALID);
+            }
+            next.speed = Some(speed as u8);
+        }
+
+        let (r, g, b) = (byte("r")?, byte("g")?, byte("b")?);
+        let (c, w) = (byte("c")?, byte("w")?);
+        if r.is_some() || g.is_some() || b.is_some() || c.is_some() || w.is_some() {
+            if !caps.supports_rgb {
+                return Err(INVALID);
+            }
+            next.r = r.or(next.r).or(Some(0));
+            next.g = g.or(next.g).or(Some(0));
+            next.b = b.or(next.b).or(Some(0));
+            next.c = c.or(next.c);
+            next.w = w.or(next.w);
+            next.temp = None;
+            next.scene_id = None;
+        }
+        if let Some(temp) = field("temp") {
+            let temp = temp as u32;
+            if !caps
+                .supports_temp
+                .as_ref()
+                .is_some_and(|range| range.contains(&temp))
+            {
+                return Err(INVALID);
+            }
+            next.temp = Some(temp);
+            next.r = None;
+            next.g = None;
+            next.b = None;
+            next.c = None;
+            next.w = None;
+            next.scene_id = None;
+        }
+        if let Some(scene_id) = field("sceneId") {
+            let supported =
+                Scene::from_id(scene_id as u8).is_some_and(|scene| caps.supports_scene(scene));
+            if !supported {
+                return Err(INVALID);
+            }
+            next.scene_id = Some(scene_id as u8);
+            next.temp = None;
+            next.r = None;
+            next.g = None;
+            next.b = None;
+        }
+
+        self.state = next;
+        Ok(())
+    }
+}
+
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .collect::<Map<String, Value>>(),
+        ),
+        value => value,
+    }
+}
+
+/// How unreliable the simulated network is.
+#[derive(Debug, Clone, Default)]
+pub struct SimConfig {
+    /// Chance between `0.0` and `1.0` that a request, or its reply, is lost.
+    pub loss: f64,
+    /// Delay before every reply.
+    pub latency: Duration,
+    /// Makes the losses repeatable.
+    pub seed: Option<u64>,
+}
+
+/// Emulates WiZ devices on local addresses so `Wizard`, the daemon and the
+/// GUI can be exercised without hardware.
+///
+/// On Linux the whole `127.0.0.0/8` range reaches loopback; other systems
+/// need an alias for each address first.
+pub struct Simulator {
+    bulbs: Vec<Arc<Mutex<SimBulb>>>,
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Simulator {
+    /// Binds every bulb's address and starts answering requests. Fails with
+    /// `InvalidInput` when `config.loss` is not between 0 and 1.
+    pub fn start(bulbs: Vec<SimBulb>, config: SimConfig) -> Result<Simulator, WizardError> {
+        if !(0.0..=1.0).contains(&config.loss) {
+            return Err(WizardError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("loss must be between 0 and 1, got {}", config.loss),
+            )));
+        }
+        let running = Arc::new(AtomicBool::new(true));
+        let mut sim = Simulator {
+            bulbs: Vec::new(),
+            running: running.clone(),
+            threads: Vec::new(),
+        };
+
+        for (idx, bulb) in bulbs.into_iter().enumerate() {
+            let addr = SocketAddr::from((bulb.ip, WIZARD_PORT));
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            // lets a GUI holding 0.0.0.0 on the same port run next to us
+            socket.set_reuse_address(true)?;
+            socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+            socket
+                .bind(&addr.into())
+                .map_err(|e| WizardError::Bind(WIZARD_PORT, e))?;
+
+            let bulb = Arc::new(Mutex::new(bulb));
+            sim.bulbs.push(bulb.clone());
+
+            let mut rng = match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(idx as u64)),
+                None => StdRng::from_entropy(),
+            };
+            let config = config.clone();
+            let running = running.clone();
+            sim.threads.push(thread::spawn(move || {
+                let mut buf = [MaybeUninit::new(0u8); 1024];
+                while running.load(Ordering::SeqCst) {
+                    let Ok((amt, src)) = socket.recv_from(&mut buf) else {
+                        continue;
+                    };
+                    if rng.gen_bool(config.loss) {
+                        continue;
+                    }
+                    let pbuf: Vec<u8> = buf[..amt]
+                        .iter()
+                        .map(|c| unsafe { c.assume_init() })
+                        .collect();
+                    let data = String::from_utf8_lossy(&pbuf);
+
+                    let Some(reply) = bulb.lock().unwrap().reply(&data) else {
+                        continue;
+                    };
+                    thread::sleep(config.latency);
+                    if rng.gen_bool(config.loss) {
+                        continue;
+                    }
+                    let _ = socket.send_to(reply.to_string().as_bytes(), &src);
+                }
+            }));
+        }
+
+        Ok(sim)
+    }
+
+    /// `count` full color bulbs on consecutive addresses from `first`.
+    pub fn start_range(
+        first: Ipv4Addr,
+        count: u32,
+        config: SimConfig,
+    ) -> Result<Simulator, WizardError> {
+        let first = u32::from(first);
+        let bulbs = (0..count)
+            .map(|i| SimBulb::new(Ipv4Addr::from(first + i)))
+            .collect();
+        Simulator::start(bulbs, config)
+    }
+
+    /// A snapshot of every simulated bulb, including its current state.
+    pub fn bulbs(&self) -> Vec<SimBulb> {
+        self.bulbs
+            .iter()
+            .map(|bulb| bulb.lock().unwrap().clone())
+            .collect()
+    }
+
+    pub fn state(&self, mac: &str) -> Option<PilotState> {
+        self.bulbs
+            .iter()
+            .map(|bulb| bulb.lock().unwrap())
+            .find(|bulb| bulb.mac == mac)
+            .map(|bulb| bulb.state.clone())
+    }
+
+    /// Stops answering and releases the ports.
+    pub fn stop(mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
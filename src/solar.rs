@@ -0,0 +1,72 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Julian day of 2000-01-01 12:00 UTC.
+const J2000: f64 = 2451545.0;
+/// Julian day of the unix epoch.
+const UNIX_EPOCH: f64 = 2440587.5;
+
+/// Where the bulbs are, for working out when the sun rises and sets.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// Degrees, north positive.
+    pub latitude: f64,
+    /// Degrees, east positive.
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+    /// The sun 6° below the horizon after sunset, when it gets too dark to
+    /// read outside.
+    CivilDusk,
+}
+
+impl SunEvent {
+    /// Sun elevation at the event, in degrees. Sunrise and sunset allow for
+    /// refraction and the sun's radius.
+    fn elevation(&self) -> f64 {
+        match self {
+            SunEvent::Sunrise | SunEvent::Sunset => -0.833,
+            SunEvent::CivilDusk => -6.0,
+        }
+    }
+}
+
+/// When `event` happens on `date` at `location`, accurate to a minute or
+/// two. `None` when the sun does not get there that day, as in polar summer
+/// or winter.
+pub fn sun_event(date: NaiveDate, event: SunEvent, location: &Location) -> Option<DateTime<Utc>> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let days = (date - epoch).num_days() as f64;
+
+    // mean solar time at the location
+    let mean = days - location.longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = J2000 + mean + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic).sin();
+
+    let declination = (ecliptic.sin() * 23.4397_f64.to_radians().sin()).asin();
+    let latitude = location.latitude.to_radians();
+    let cos_hour = (event.elevation().to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+    let hour = cos_hour.acos().to_degrees() / 360.0;
+
+    let julian = match event {
+        SunEvent::Sunrise => transit - hour,
+        SunEvent::Sunset | SunEvent::CivilDusk => transit + hour,
+    };
+    let secs = ((julian - UNIX_EPOCH) * 86400.0).round() as i64;
+    DateTime::from_timestamp(secs, 0)
+}
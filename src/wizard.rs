@@ -1,156 +1,508 @@
-use ipnet::Ipv4Net;
 use socket2::{Domain, Protocol, Socket, Type};
 
-use std::io::Write;
-
-use local_ip_address::local_ip;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc, Mutex};
 use std::thread;
-use std::{mem::MaybeUninit, net::SocketAddr, time::Duration};
+use std::time::Duration;
+use std::time::Instant;
 
+use chrono::NaiveDateTime;
 use interprocess::local_socket::LocalSocketStream;
 
-use crate::program::Action;
+use crate::program::Program;
 use crate::{
     bulb::Bulb,
-    daemon::{Msg, DAEMONNAME},
-    pilot::{Method, Pilot},
+    daemon::{
+        read_frame, write_frame, Answer, DaemonError, DaemonStatus, Msg, ProgramId, ProgramInfo,
+        Request, Response, ResumeFrom, StepInfo, DAEMONNAME, PROTOCOL_VERSION,
+    },
+    discovery::{Collector, Discovery, DISCOVERY_METHODS, METADATA_TIMEOUT},
+    error::WizardError,
+    pilot::{Method, Pilot, PilotState},
+    reply::Replies,
+    schedule::{Schedule, ScheduleId, ScheduleInfo},
+    solar::Location,
 };
 pub const WIZARD_PORT: u16 = 38899;
+
+pub(crate) const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+pub(crate) const ACK_ATTEMPTS: u32 = 3;
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_millis(250);
+
+pub(crate) fn bulb_addr(ip: &str) -> Result<SocketAddr, WizardError> {
+    format!("{}:{}", ip, WIZARD_PORT)
+        .parse()
+        .map_err(|_| WizardError::InvalidAddress(ip.to_string()))
+}
+
+pub(crate) fn bind_socket(port: u16) -> Result<Socket, WizardError> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket
+        .bind(&addr.into())
+        .map_err(|e| WizardError::Bind(port, e))?;
+    Ok(socket)
+}
+
+/// Resolves every address to the WiZ port.
+pub(crate) fn bulb_addrs(ips: &[Ipv4Addr]) -> Vec<SocketAddr> {
+    ips.iter()
+        .map(|ip| SocketAddr::from((*ip, WIZARD_PORT)))
+        .collect()
+}
+
+/// Sends `data` to every address, failing only if none of the sends worked.
+fn send_all(socket: &Socket, data: &str, addrs: &[SocketAddr]) -> Result<(), WizardError> {
+    let mut last_err = None;
+    let mut sent = false;
+    for addr in addrs.iter() {
+        match socket.send_to(data.as_bytes(), &(*addr).into()) {
+            Ok(_) => sent = true,
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match last_err {
+        Some(e) if !sent => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn daemon_send(
+    stream: &mut LocalSocketStream,
+    request: &Request,
+) -> Result<(), WizardError> {
+    let data = serde_json::to_vec(request)?;
+    write_frame(stream, &data)?;
+    Ok(())
+}
+
+/// Reads one answer, turning a [`Response::Error`] into an `Err`.
+pub(crate) fn daemon_recv(stream: &mut LocalSocketStream) -> Result<Response, WizardError> {
+    let data = read_frame(stream)?
+        .ok_or_else(|| WizardError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)))?;
+    let answer: Answer = serde_json::from_slice(&data)?;
+    if answer.version != PROTOCOL_VERSION {
+        return Err(WizardError::Daemon(DaemonError::Version {
+            daemon: answer.version,
+            client: PROTOCOL_VERSION,
+        }));
+    }
+    match answer.response {
+        Response::Error(e) => Err(WizardError::Daemon(e)),
+        response => Ok(response),
+    }
+}
+
+pub(crate) fn daemon_call(
+    stream: &mut LocalSocketStream,
+    msg: Msg,
+) -> Result<Response, WizardError> {
+    daemon_send(stream, &Request::new(msg))?;
+    daemon_recv(stream)
+}
+
+pub(crate) fn unexpected(response: Response) -> WizardError {
+    WizardError::Daemon(DaemonError::Unexpected(format!("{:?}", response)))
+}
+
+#[derive(Clone)]
 pub struct Wizard {
-    socket: Arc<Mutex<Socket>>,
+    socket: Arc<Socket>,
+    replies: Replies,
+    running: Arc<AtomicBool>,
     pub daemon: Arc<Mutex<Option<LocalSocketStream>>>,
     pub bulbs: Arc<Mutex<Vec<Bulb>>>,
     pub searching: Arc<AtomicBool>,
+    pub discovery: Discovery,
 }
 
 impl Wizard {
-    pub fn new() -> Wizard {
-        let addr: SocketAddr = format!("0.0.0.0:{}", WIZARD_PORT).parse().unwrap();
-
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-        socket.set_reuse_address(true).unwrap();
-        socket.set_broadcast(true).unwrap();
-        socket
-            .set_read_timeout(Some(Duration::from_secs(2)))
-            .unwrap();
-        socket.bind(&addr.into()).unwrap();
-
-        Wizard {
-            socket: Arc::new(Mutex::new(socket)),
-            daemon: Arc::new(Mutex::new(LocalSocketStream::connect(DAEMONNAME).ok())),
+    /// Binds the WiZ port and connects to the daemon if it is running.
+    pub fn new() -> Result<Wizard, WizardError> {
+        let wiz = Wizard::bind(WIZARD_PORT)?;
+        *wiz.daemon.lock().unwrap() = LocalSocketStream::connect(DAEMONNAME).ok();
+        Ok(wiz)
+    }
+
+    /// Binds `port` without touching the daemon. Bulbs answer to the port a
+    /// request came from, so `0` gives a client that can run next to the GUI.
+    pub fn bind(port: u16) -> Result<Wizard, WizardError> {
+        let socket = bind_socket(port)?;
+        socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+
+        let socket = Arc::new(socket);
+        let running = Arc::new(AtomicBool::new(true));
+        let replies = Replies::spawn(socket.clone(), running.clone());
+
+        Ok(Wizard {
+            socket,
+            replies,
+            running,
+            daemon: Arc::new(Mutex::new(None)),
             bulbs: Arc::new(Mutex::new(Vec::new())),
             searching: Arc::new(AtomicBool::new(false)),
-        }
+            discovery: Discovery::default(),
+        })
     }
 
-    pub fn daemon_connect(&self) {
+    pub fn daemon_connect(&self) -> Result<(), WizardError> {
         let daemon = self.daemon.clone();
+        let mut daemon = daemon.lock().unwrap();
 
-        *daemon.lock().unwrap() = LocalSocketStream::connect(DAEMONNAME).ok();
+        *daemon = None;
+        *daemon = Some(LocalSocketStream::connect(DAEMONNAME)?);
+        Ok(())
     }
 
-    pub fn daemon_shutdown(&self) {
+    pub fn daemon_shutdown(&self) -> Result<(), WizardError> {
         let daemon = self.daemon.clone();
 
         let daemon = daemon.lock().unwrap().take();
 
-        match daemon {
-            Some(mut daemon) => {
-                let msg = Msg::Stop;
-                let data = serde_json::to_string(&msg).unwrap();
-                let _ = daemon.write_all(data.as_bytes());
-            }
-            None => {}
+        let mut daemon = daemon.ok_or(WizardError::DaemonNotConnected)?;
+        match daemon_call(&mut daemon, Msg::Stop)? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Checks that the daemon is still there and answering.
+    pub fn daemon_ping(&self) -> Result<(), WizardError> {
+        match self.daemon_request(Msg::Ping)? {
+            Response::Pong => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_status(&self) -> Result<DaemonStatus, WizardError> {
+        match self.daemon_request(Msg::Status)? {
+            Response::Status(status) => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs `program` on `bulb`, replacing the program it ran before.
+    pub fn daemon_run_program(
+        &self,
+        program: Program,
+        bulb: Bulb,
+    ) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::Run(program, bulb))? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs `program` on every bulb in `bulbs` in step.
+    pub fn daemon_run_group(
+        &self,
+        program: Program,
+        bulbs: Vec<Bulb>,
+    ) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::RunGroup(program, bulbs))? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_list_programs(&self) -> Result<Vec<ProgramInfo>, WizardError> {
+        match self.daemon_request(Msg::ListPrograms)? {
+            Response::Programs(programs) => Ok(programs),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_current_step(&self, id: ProgramId) -> Result<StepInfo, WizardError> {
+        match self.daemon_request(Msg::CurrentStep(id))? {
+            Response::Step(step) => Ok(step),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_pause(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::Pause(id))
+    }
+
+    pub fn daemon_resume(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::Resume(id))
+    }
+
+    pub fn daemon_stop_program(&self, id: ProgramId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::StopProgram(id))
+    }
+
+    /// Chooses where the program picks up when the daemon restarts.
+    pub fn daemon_set_resume(&self, id: ProgramId, resume: ResumeFrom) -> Result<(), WizardError> {
+        self.daemon_done(Msg::SetResume(id, resume))
+    }
+
+    pub fn daemon_add_schedule(&self, schedule: Schedule) -> Result<ScheduleId, WizardError> {
+        match self.daemon_request(Msg::AddSchedule(schedule))? {
+            Response::Scheduled(id) => Ok(id),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_list_schedules(&self) -> Result<Vec<ScheduleInfo>, WizardError> {
+        match self.daemon_request(Msg::ListSchedules)? {
+            Response::Schedules(schedules) => Ok(schedules),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn daemon_remove_schedule(&self, id: ScheduleId) -> Result<(), WizardError> {
+        self.daemon_done(Msg::RemoveSchedule(id))
+    }
+
+    /// Where the bulbs are, for schedules that follow the sun.
+    pub fn daemon_set_location(&self, location: Option<Location>) -> Result<(), WizardError> {
+        self.daemon_done(Msg::SetLocation(location))
+    }
+
+    /// When `schedule` would next start, in local time.
+    pub fn daemon_preview(
+        &self,
+        schedule: Schedule,
+        count: usize,
+    ) -> Result<Vec<NaiveDateTime>, WizardError> {
+        match self.daemon_request(Msg::Preview(schedule, count))? {
+            Response::Preview(starts) => Ok(starts),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Runs a program written in the [`crate::script`] format on `bulbs`.
+    pub fn daemon_import(&self, text: String, bulbs: Vec<Bulb>) -> Result<ProgramId, WizardError> {
+        match self.daemon_request(Msg::Import(text, bulbs))? {
+            Response::Started(id) => Ok(id),
+            response => Err(unexpected(response)),
         }
     }
 
-    pub fn daemon_run_program(&self, program: Vec<Action>, bulb_ip: String) {
+    /// A running program in the [`crate::script`] format.
+    pub fn daemon_export(&self, id: ProgramId) -> Result<String, WizardError> {
+        match self.daemon_request(Msg::Export(id))? {
+            Response::Script(text) => Ok(text),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn daemon_done(&self, msg: Msg) -> Result<(), WizardError> {
+        match self.daemon_request(msg)? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends `msg` and waits for the daemon's answer.
+    fn daemon_request(&self, msg: Msg) -> Result<Response, WizardError> {
         let daemon = self.daemon.clone();
 
         let mut daemon = daemon.lock().unwrap();
 
-        if let Some(daemon) = daemon.as_mut() {
-            let msg = Msg::Run(program, bulb_ip);
-            let data = serde_json::to_string(&msg).unwrap();
-            let _ = daemon.write_all(data.as_bytes());
+        let stream = daemon.as_mut().ok_or(WizardError::DaemonNotConnected)?;
+        let result = daemon_call(stream, msg);
+        if let Err(WizardError::Io(_)) | Err(WizardError::Serialize(_)) = result {
+            // the daemon went away, so stop reporting it as connected
+            *daemon = None;
+        }
+        result
+    }
+
+    pub fn set_pilot(&self, bulb: Bulb, pilot: Pilot) -> Result<(), WizardError> {
+        let data = pilot.build_for(&bulb.capabilities())?;
+        let addr = bulb_addr(&bulb.ip)?;
+        self.socket.send_to(data.as_bytes(), &addr.into())?;
+        Ok(())
+    }
+
+    /// Like [`Wizard::set_pilot`] but waits for the bulb to acknowledge it,
+    /// resending with a doubling timeout when no answer arrives.
+    ///
+    /// If the bulb stays silent it is looked up again by MAC, in case DHCP
+    /// gave it a new address, and `bulb.ip` is updated before a last try.
+    pub fn set_pilot_ack(&self, bulb: &mut Bulb, pilot: &Pilot) -> Result<(), WizardError> {
+        match self.send_ack(bulb, pilot) {
+            Err(WizardError::Timeout) | Err(WizardError::Io(_)) => {
+                self.resolve(bulb)?;
+                self.send_ack(bulb, pilot)
+            }
+            result => result,
+        }
+    }
+
+    fn send_ack(&self, bulb: &Bulb, pilot: &Pilot) -> Result<(), WizardError> {
+        let data = pilot.build_for(&bulb.capabilities())?;
+        let addr = bulb_addr(&bulb.ip)?;
+
+        let sub = self
+            .replies
+            .subscribe(Some(bulb.ip.clone()), pilot.method.clone());
+
+        let mut timeout = ACK_TIMEOUT;
+        for _ in 0..ACK_ATTEMPTS {
+            self.socket.send_to(data.as_bytes(), &addr.into())?;
+
+            if let Ok(reply) = sub.rx.recv_timeout(timeout) {
+                return reply.ack().map_err(WizardError::Rejected);
+            }
+            timeout *= 2;
+        }
+
+        Err(WizardError::Timeout)
+    }
+
+    /// Finds the bulb with the same MAC on the network and takes its current
+    /// address, updating the matching entry of [`Wizard::bulbs`] too.
+    pub fn resolve(&self, bulb: &mut Bulb) -> Result<(), WizardError> {
+        let broadcasts = bulb_addrs(&self.discovery.broadcast_addresses()?);
+        let sweep = bulb_addrs(&self.discovery.sweep_addresses()?);
+
+        let sub = self.replies.subscribe(None, Method::GetDevInfo);
+        let data = Pilot::new(Method::GetDevInfo).build()?;
+
+        for round in [broadcasts, sweep] {
+            if round.is_empty() {
+                continue;
+            }
+            send_all(&self.socket, &data, &round)?;
+
+            let deadline = Instant::now() + REPLY_TIMEOUT;
+            while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                let Ok(reply) = sub.rx.recv_timeout(timeout) else {
+                    break;
+                };
+                let Some(found) = Bulb::parse(reply.ip, &reply.data) else {
+                    continue;
+                };
+                if found.mac == bulb.mac {
+                    bulb.merge(&found);
+                    if let Some(known) = self
+                        .bulbs
+                        .lock()
+                        .unwrap()
+                        .iter_mut()
+                        .find(|b| b.mac == bulb.mac)
+                    {
+                        known.merge(&found);
+                    }
+                    return Ok(());
+                }
+            }
         }
+
+        Err(WizardError::NotFound(bulb.mac.clone()))
     }
 
-    pub fn set_pilot(&self, bulb: Bulb, pilot: Pilot) {
-        let data = pilot.build();
-        let addr: SocketAddr = format!("{}:{}", bulb.ip, WIZARD_PORT).parse().unwrap();
-        let _ = self
-            .socket
-            .lock()
-            .unwrap()
-            .send_to(data.to_string().as_bytes(), &addr.into());
+    /// Asks the bulb for its current state and waits for the reply.
+    pub fn get_pilot(&self, bulb: &Bulb) -> Result<PilotState, WizardError> {
+        let pilot = Pilot::new(Method::GetPilot);
+        let addr = bulb_addr(&bulb.ip)?;
+
+        let sub = self
+            .replies
+            .subscribe(Some(bulb.ip.clone()), Method::GetPilot);
+        let data = pilot.build()?;
+        self.socket.send_to(data.as_bytes(), &addr.into())?;
+
+        let reply = sub
+            .rx
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| WizardError::Timeout)?;
+        PilotState::parse(&reply.data)
+            .ok_or_else(|| WizardError::Rejected(String::from("unreadable getPilot reply")))
+    }
+
+    /// Like [`Wizard::get_pilot`], but on its own thread, looking the bulb
+    /// up by MAC if its saved address no longer answers. The bulb comes back
+    /// with the address it was reached at.
+    pub fn fetch_pilot(&self, mut bulb: Bulb) -> Receiver<(Bulb, Result<PilotState, WizardError>)> {
+        let (tx, rx) = mpsc::channel();
+        let wiz = self.clone();
+        thread::spawn(move || {
+            let state = match wiz.get_pilot(&bulb) {
+                // the saved ip may be stale, look the bulb up by mac
+                Err(WizardError::Timeout) => {
+                    wiz.resolve(&mut bulb).and_then(|_| wiz.get_pilot(&bulb))
+                }
+                result => result,
+            };
+            let _ = tx.send((bulb, state));
+        });
+        rx
     }
 
     pub fn cleanup(&self) {
-        let _ = self
-            .socket
-            .lock()
-            .unwrap()
-            .shutdown(std::net::Shutdown::Both);
+        self.running.store(false, Ordering::SeqCst);
+        let _ = self.socket.shutdown(std::net::Shutdown::Both);
     }
 
-    pub fn discover(&mut self) {
-        let localip = local_ip().unwrap();
-        let network = Ipv4Net::new(localip.to_string().parse().unwrap(), 24).unwrap();
-        let broadcast_addr = network.broadcast().to_string();
+    /// Looks for bulbs on every target in [`Wizard::discovery`], sweeping
+    /// the configured ranges host by host if no broadcast gets an answer.
+    ///
+    /// Bulbs are merged into [`Wizard::bulbs`] as their replies arrive, and
+    /// sent on the returned channel each time one is found or its details
+    /// (module, firmware, signal strength) come in. Bulbs that miss a run
+    /// stay in the list.
+    pub fn discover(&mut self) -> Result<Receiver<Bulb>, WizardError> {
+        let broadcasts = bulb_addrs(&self.discovery.broadcast_addresses()?);
+        let sweep = bulb_addrs(&self.discovery.sweep_addresses()?);
+
+        let sub = self.replies.subscribe_methods(None, &DISCOVERY_METHODS);
+        let data = Pilot::new(Method::GetDevInfo).build()?;
+        send_all(&self.socket, &data, &broadcasts)?;
 
+        let (tx, rx) = mpsc::channel();
         let nbulbs = self.bulbs.clone();
         let nsocket = self.socket.clone();
         let searching = self.searching.clone();
+        searching.store(true, Ordering::SeqCst);
         thread::spawn(move || {
-            searching.store(true, Ordering::SeqCst);
-            let pilot = Pilot::new(Method::GetDevInfo);
-            let addr: SocketAddr = format!("{}:{}", broadcast_addr, WIZARD_PORT)
-                .parse()
-                .unwrap();
-            nsocket
-                .lock()
-                .unwrap()
-                .send_to(pilot.build().as_bytes(), &addr.into())
-                .unwrap();
-
-            let mut bulbs: Vec<Bulb> = Vec::new();
-
-            let mut buf = [MaybeUninit::new(0u8); 1024];
-            loop {
-                match nsocket.lock().unwrap().recv_from(&mut buf) {
-                    Ok((_amt, src)) => {
-                        let src_ip = src.as_socket_ipv4().unwrap();
-                        if src_ip.ip() == &localip {
-                            continue;
-                        }
-                        let pbuf = buf.map(|c| unsafe { c.assume_init() });
-                        let data = String::from_utf8(pbuf.to_vec()).unwrap();
-                        let bulb = Bulb::parse(src_ip.ip().to_string(), &data);
-                        if let Some(bulb) = bulb {
-                            bulbs.push(bulb);
+            let mut collector = Collector::new();
+
+            let collect = |collector: &mut Collector| {
+                let mut deadline = Instant::now() + REPLY_TIMEOUT;
+                while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                    let Ok(reply) = sub.rx.recv_timeout(timeout) else {
+                        break;
+                    };
+                    let Some((bulb, new)) = collector.handle(&reply) else {
+                        continue;
+                    };
+
+                    if new {
+                        if let Ok(addr) = bulb_addr(&bulb.ip) {
+                            for method in [Method::GetSystemConfig, Method::GetPilot] {
+                                let query = Pilot::new(method).build().unwrap();
+                                let _ = nsocket.send_to(query.as_bytes(), &addr.into());
+                            }
                         }
+                        deadline = deadline.max(Instant::now() + METADATA_TIMEOUT);
                     }
-                    Err(_e) => {
-                        break;
+
+                    let mut known = nbulbs.lock().unwrap();
+                    match known.iter_mut().find(|b| b.mac == bulb.mac) {
+                        Some(known) => known.merge(&bulb),
+                        None => known.push(bulb.clone()),
                     }
+                    drop(known);
+                    let _ = tx.send(bulb);
                 }
-            }
+            };
 
-            let mut map = std::collections::HashMap::new();
-            for bulb in nbulbs.lock().unwrap().iter() {
-                map.insert(bulb.mac.clone(), bulb.name.clone());
+            collect(&mut collector);
+            if collector.bulbs.is_empty() && !sweep.is_empty() {
+                let _ = send_all(&nsocket, &data, &sweep);
+                collect(&mut collector);
             }
 
-            for bulb in bulbs.iter_mut() {
-                if let Some(name) = map.get(&bulb.mac) {
-                    bulb.name = name.clone();
-                }
-            }
-
-            *nbulbs.lock().unwrap() = bulbs;
             searching.store(false, Ordering::SeqCst);
         });
+
+        Ok(rx)
     }
 }
@@ -0,0 +1,530 @@
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+use std::path::PathBuf;
+
+use wizard_rs::bulb::Bulb;
+use wizard_rs::capabilities::Capabilities;
+use wizard_rs::pilot::{Method, Pilot, PilotError, PilotState};
+use wizard_rs::reply::Reply;
+use wizard_rs::scenes::Scene;
+
+/// Replies of one device, keyed by method, plus what should be read from them.
+///
+/// The fixtures are written by hand rather than captured from firmware, see
+/// `tests/fixtures/README.md`.
+struct Fixture {
+    name: String,
+    doc: Value,
+}
+
+impl Fixture {
+    fn reply(&self, method: &str) -> String {
+        self.doc[method].to_string()
+    }
+
+    fn expect(&self, field: &str) -> &Value {
+        &self.doc["expect"][field]
+    }
+}
+
+fn fixtures() -> Vec<Fixture> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures: Vec<Fixture> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let data = std::fs::read_to_string(&path).unwrap();
+            Fixture {
+                name: path.file_stem().unwrap().to_string_lossy().to_string(),
+                doc: serde_json::from_str(&data).unwrap(),
+            }
+        })
+        .collect();
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    assert!(!fixtures.is_empty());
+    fixtures
+}
+
+/// Pads `data` the way it sits in the receive buffer.
+fn padded(data: &str) -> String {
+    let mut buf = String::from("\0");
+    buf.push_str(data);
+    buf.extend(std::iter::repeat_n('\0', 1024 - buf.len()));
+    buf
+}
+
+fn built(pilot: &Pilot, caps: &Capabilities) -> Result<Value, PilotError> {
+    pilot
+        .build_for(caps)
+        .map(|data| serde_json::from_str(&data).unwrap())
+}
+
+#[test]
+fn discovery_replies() {
+    for fixture in fixtures() {
+        for pad in [false, true] {
+            let reply = |method: &str| match pad {
+                true => padded(&fixture.reply(method)),
+                false => fixture.reply(method),
+            };
+
+            let mut bulb = Bulb::parse(String::from("10.0.0.2"), &reply("getDevInfo"))
+                .unwrap_or_else(|| panic!("{}: getDevInfo", fixture.name));
+            assert_eq!(bulb.mac, fixture.expect("mac").as_str().unwrap());
+            assert_eq!(bulb.name, bulb.mac);
+            assert_eq!(bulb.ip, "10.0.0.2");
+
+            assert!(bulb.update_metadata(&reply("getSystemConfig")));
+            assert_eq!(
+                bulb.module_name.as_deref(),
+                fixture.expect("moduleName").as_str(),
+                "{}",
+                fixture.name
+            );
+            assert_eq!(
+                bulb.fw_version.as_deref(),
+                fixture.expect("fwVersion").as_str()
+            );
+
+            assert!(bulb.update_metadata(&reply("getPilot")));
+            assert_eq!(
+                bulb.rssi.map(i64::from),
+                fixture.expect("rssi").as_i64(),
+                "{}",
+                fixture.name
+            );
+            assert!(!bulb.update_metadata(&reply("getPilot")));
+        }
+    }
+}
+
+#[test]
+fn pilot_replies() {
+    for fixture in fixtures() {
+        let result = &fixture.doc["getPilot"]["result"];
+        for data in [
+            fixture.reply("getPilot"),
+            padded(&fixture.reply("getPilot")),
+        ] {
+            let state =
+                PilotState::parse(&data).unwrap_or_else(|| panic!("{}: getPilot", fixture.name));
+            assert_eq!(state.state, result["state"].as_bool().unwrap());
+            assert_eq!(state.dimming.map(u64::from), result["dimming"].as_u64());
+            assert_eq!(state.temp.map(u64::from), result["temp"].as_u64());
+            assert_eq!(state.r.map(u64::from), result["r"].as_u64());
+            assert_eq!(state.scene_id.map(u64::from), result["sceneId"].as_u64());
+            assert_eq!(state.speed.map(u64::from), result["speed"].as_u64());
+            assert_eq!(state.rssi.map(i64::from), result["rssi"].as_i64());
+
+            // what we send back must be accepted by the same device
+            let caps =
+                Capabilities::from_module_name(fixture.expect("moduleName").as_str().unwrap());
+            state.to_pilot().build_for(&caps).unwrap();
+        }
+
+        // a getPilot parser must not accept other methods
+        assert!(PilotState::parse(&fixture.reply("getSystemConfig")).is_none());
+    }
+}
+
+#[test]
+fn push_and_ack_replies() {
+    for fixture in fixtures() {
+        let (mac, state) = PilotState::parse_sync(&padded(&fixture.reply("syncPilot")))
+            .unwrap_or_else(|| panic!("{}: syncPilot", fixture.name));
+        assert_eq!(mac, fixture.expect("mac").as_str().unwrap());
+        assert_eq!(
+            state.state,
+            fixture.doc["syncPilot"]["params"]["state"]
+                .as_bool()
+                .unwrap()
+        );
+        assert!(PilotState::parse_sync(&fixture.reply("getPilot")).is_none());
+
+        let ack = Reply::parse(
+            String::from("10.0.0.2"),
+            &padded(&fixture.reply("setPilot")),
+        )
+        .unwrap();
+        assert_eq!(ack.method, "setPilot");
+        assert_eq!(ack.ack(), Ok(()));
+    }
+}
+
+#[test]
+fn malformed_replies() {
+    for data in ["", "\0\0\0", "not json", "{}", r#"{"method":"getDevInfo"}"#] {
+        assert!(
+            Bulb::parse(String::from("10.0.0.2"), data).is_none(),
+            "{:?}",
+            data
+        );
+        assert!(PilotState::parse(data).is_none(), "{:?}", data);
+        assert!(PilotState::parse_sync(data).is_none(), "{:?}", data);
+    }
+
+    let rejected =
+        r#"{"method":"setPilot","env":"pro","error":{"code":-32600,"message":"Invalid Request"}}"#;
+    let reply = Reply::parse(String::from("10.0.0.2"), rejected).unwrap();
+    assert_eq!(reply.ack(), Err(String::from("Invalid Request")));
+
+    let silent = r#"{"method":"setPilot","env":"pro","result":{}}"#;
+    let reply = Reply::parse(String::from("10.0.0.2"), silent).unwrap();
+    assert!(reply.ack().is_err());
+}
+
+#[test]
+fn query_requests() {
+    for (method, expected) in [
+        (Method::GetPilot, json!({"method": "getPilot"})),
+        (Method::GetDevInfo, json!({"method": "getDevInfo"})),
+        (
+            Method::GetSystemConfig,
+            json!({"method": "getSystemConfig"}),
+        ),
+    ] {
+        // queries carry no params, whatever the pilot holds
+        let mut pilot = Pilot::new(method);
+        pilot.set_rgb(255, 0, 0);
+        pilot.set_temp(3000);
+        assert_eq!(built(&pilot, &Capabilities::all()), Ok(expected));
+    }
+}
+
+/// A setPilot with `f` applied.
+fn set(f: impl FnOnce(&mut Pilot)) -> Pilot {
+    let mut pilot = Pilot::new(Method::SetPilot);
+    f(&mut pilot);
+    pilot
+}
+
+#[test]
+fn set_pilot_requests() {
+    let all = Capabilities::all();
+    let rgb = Capabilities::from_module_name("ESP03_SHRGB1C_01");
+    let tw = Capabilities::from_module_name("ESP01_SHTW1C_31");
+    let dw = Capabilities::from_module_name("ESP06_SHDW9_01");
+    let socket = Capabilities::from_module_name("ESP10_SOCKET_06");
+
+    let on = set(|_| {});
+    let off = set(|p| p.set_state(false));
+    let color = set(|p| {
+        p.set_rgb(255, 128, 0);
+        p.set_brightness(0.29);
+    });
+    let mixed = set(|p| {
+        p.set_rgb(255, 128, 0);
+        p.set_cold(10);
+        p.set_warm(200);
+    });
+    let white = set(|p| {
+        p.set_cold(10);
+        p.set_warm(200);
+    });
+    let scene = set(|p| {
+        p.set_scene(Scene::Wakeup);
+        p.set_speed(1.5);
+    });
+
+    let cases = [
+        (
+            on.clone(),
+            &rgb,
+            json!({"state": true, "dimming": 100, "speed": 90}),
+        ),
+        (
+            on.clone(),
+            &dw,
+            json!({"state": true, "dimming": 100, "speed": 90}),
+        ),
+        (on, &socket, json!({"state": true})),
+        (off.clone(), &rgb, json!({"state": false, "speed": 90})),
+        (off, &socket, json!({"state": false})),
+        // dimming is not sent when switching off, so it is not checked then
+        (
+            set(|p| {
+                p.set_state(false);
+                p.set_brightness(0.05);
+            }),
+            &rgb,
+            json!({"state": false, "speed": 90}),
+        ),
+        (
+            set(|p| p.set_brightness(0.1)),
+            &rgb,
+            json!({"state": true, "dimming": 10, "speed": 90}),
+        ),
+        (
+            color.clone(),
+            &rgb,
+            json!({"state": true, "dimming": 29, "r": 255, "g": 128, "b": 0, "speed": 90}),
+        ),
+        (
+            mixed,
+            &rgb,
+            json!({"state": true, "dimming": 100, "r": 255, "g": 128, "b": 0, "c": 10, "w": 200, "speed": 90}),
+        ),
+        (
+            white,
+            &rgb,
+            json!({"state": true, "dimming": 100, "c": 10, "w": 200, "speed": 90}),
+        ),
+        (
+            set(|p| p.set_temp(2700)),
+            &tw,
+            json!({"state": true, "dimming": 100, "temp": 2700, "speed": 90}),
+        ),
+        (
+            set(|p| p.set_temp(4000)),
+            &rgb,
+            json!({"state": true, "dimming": 100, "temp": 4000, "speed": 90}),
+        ),
+        (
+            set(|p| p.set_temp(2200)),
+            &all,
+            json!({"state": true, "dimming": 100, "temp": 2200, "speed": 90}),
+        ),
+        (
+            set(|p| p.set_temp(6500)),
+            &all,
+            json!({"state": true, "dimming": 100, "temp": 6500, "speed": 90}),
+        ),
+        (
+            scene.clone(),
+            &rgb,
+            json!({"state": true, "dimming": 100, "sceneId": 9, "speed": 150}),
+        ),
+        (
+            scene.clone(),
+            &tw,
+            json!({"state": true, "dimming": 100, "sceneId": 9, "speed": 150}),
+        ),
+        (
+            scene,
+            &dw,
+            json!({"state": true, "dimming": 100, "sceneId": 9, "speed": 150}),
+        ),
+        // speed goes out without a scene too, where effects are supported
+        (
+            set(|p| {
+                p.set_rgb(255, 128, 0);
+                p.set_speed(1.5);
+            }),
+            &rgb,
+            json!({"state": true, "dimming": 100, "r": 255, "g": 128, "b": 0, "speed": 150}),
+        ),
+        (set(|p| p.set_speed(1.5)), &socket, json!({"state": true})),
+    ];
+    for (pilot, caps, params) in cases {
+        let expected = json!({"method": "setPilot", "params": params});
+        assert_eq!(
+            built(&pilot, caps),
+            Ok(expected),
+            "{:?} on {:?}",
+            pilot,
+            caps.kind
+        );
+    }
+}
+
+#[test]
+fn invalid_set_pilot_requests() {
+    let all = Capabilities::all();
+    let tw = Capabilities::from_module_name("ESP01_SHTW1C_31");
+    let dw = Capabilities::from_module_name("ESP06_SHDW9_01");
+    let socket = Capabilities::from_module_name("ESP10_SOCKET_06");
+
+    let cases = [
+        (
+            set(|p| {
+                p.set_rgb(255, 0, 0);
+                p.set_temp(3000);
+            }),
+            &all,
+            PilotError::Conflict("temp", "rgb"),
+        ),
+        (
+            set(|p| {
+                p.set_temp(3000);
+                p.set_cold(10);
+            }),
+            &all,
+            PilotError::Conflict("temp", "c/w"),
+        ),
+        (
+            set(|p| {
+                p.set_scene(Scene::Ocean);
+                p.set_rgb(0, 0, 255);
+            }),
+            &all,
+            PilotError::Conflict("sceneId", "rgb"),
+        ),
+        (
+            set(|p| {
+                p.set_scene(Scene::Ocean);
+                p.set_temp(3000);
+            }),
+            &all,
+            PilotError::Conflict("sceneId", "temp"),
+        ),
+        (
+            set(|p| {
+                p.set_scene(Scene::Ocean);
+                p.set_warm(200);
+            }),
+            &all,
+            PilotError::Conflict("sceneId", "c/w"),
+        ),
+        (
+            set(|p| p.set_brightness(0.05)),
+            &all,
+            PilotError::OutOfRange("dimming"),
+        ),
+        (
+            set(|p| p.set_temp(2199)),
+            &all,
+            PilotError::OutOfRange("temp"),
+        ),
+        (
+            set(|p| p.set_temp(6501)),
+            &all,
+            PilotError::OutOfRange("temp"),
+        ),
+        (
+            set(|p| p.set_temp(2200)),
+            &tw,
+            PilotError::OutOfRange("temp"),
+        ),
+        (
+            set(|p| p.set_rgb(0, 255, 0)),
+            &tw,
+            PilotError::Unsupported("rgb"),
+        ),
+        (set(|p| p.set_cold(10)), &tw, PilotError::Unsupported("c/w")),
+        (
+            set(|p| p.set_temp(3000)),
+            &dw,
+            PilotError::Unsupported("temp"),
+        ),
+        (
+            set(|p| p.set_scene(Scene::Ocean)),
+            &tw,
+            PilotError::Unsupported("sceneId"),
+        ),
+        (
+            set(|p| p.set_scene(Scene::Cozy)),
+            &socket,
+            PilotError::Unsupported("sceneId"),
+        ),
+    ];
+    for (pilot, caps, error) in cases {
+        assert_eq!(
+            built(&pilot, caps),
+            Err(error),
+            "{:?} on {:?}",
+            pilot,
+            caps.kind
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Mode {
+    Plain,
+    Rgb(u8, u8, u8),
+    Mixed(u8, u8, u8, u8, u8),
+    Temp(u32),
+    Scene(Scene, u8),
+}
+
+fn scenes() -> impl Strategy<Value = Scene> {
+    (1u8..=32).prop_map(|id| Scene::from_id(id).unwrap())
+}
+
+fn modes() -> impl Strategy<Value = Mode> {
+    prop_oneof![
+        Just(Mode::Plain),
+        any::<(u8, u8, u8)>().prop_map(|(r, g, b)| Mode::Rgb(r, g, b)),
+        any::<(u8, u8, u8, u8, u8)>().prop_map(|(r, g, b, c, w)| Mode::Mixed(r, g, b, c, w)),
+        (2200u32..=6500).prop_map(Mode::Temp),
+        (scenes(), 20u8..=200).prop_map(|(scene, speed)| Mode::Scene(scene, speed)),
+    ]
+}
+
+fn pilots() -> impl Strategy<Value = Pilot> {
+    (any::<bool>(), 10u8..=100, modes()).prop_map(|(state, dimming, mode)| {
+        let mut pilot = Pilot::new(Method::SetPilot);
+        pilot.set_state(state);
+        pilot.set_brightness(dimming as f32 / 100.0);
+        match mode {
+            Mode::Plain => {}
+            Mode::Rgb(r, g, b) => pilot.set_rgb(r, g, b),
+            Mode::Mixed(r, g, b, c, w) => {
+                pilot.set_rgb(r, g, b);
+                pilot.set_cold(c);
+                pilot.set_warm(w);
+            }
+            Mode::Temp(temp) => pilot.set_temp(temp),
+            Mode::Scene(scene, speed) => {
+                pilot.set_scene(scene);
+                pilot.set_speed(speed as f32 / 100.0);
+            }
+        }
+        pilot
+    })
+}
+
+/// What a bulb would report after applying `request`.
+fn as_reply(request: &Value) -> String {
+    json!({"method": "getPilot", "env": "pro", "result": request["params"]}).to_string()
+}
+
+proptest! {
+    #[test]
+    fn rgb_survives_build(r: u8, g: u8, b: u8) {
+        let mut pilot = Pilot::new(Method::SetPilot);
+        pilot.set_rgb(r, g, b);
+        let params = &built(&pilot, &Capabilities::all()).unwrap()["params"];
+        prop_assert_eq!(params["r"].as_u64(), Some(r as u64));
+        prop_assert_eq!(params["g"].as_u64(), Some(g as u64));
+        prop_assert_eq!(params["b"].as_u64(), Some(b as u64));
+    }
+
+    #[test]
+    fn dimming_survives_build(dimming in 10u8..=100) {
+        let mut pilot = Pilot::new(Method::SetPilot);
+        pilot.set_brightness(dimming as f32 / 100.0);
+        let params = &built(&pilot, &Capabilities::all()).unwrap()["params"];
+        prop_assert_eq!(params["dimming"].as_u64(), Some(dimming as u64));
+    }
+
+    #[test]
+    fn pilot_round_trips_through_json(pilot in pilots()) {
+        let caps = Capabilities::all();
+        let request = built(&pilot, &caps).unwrap();
+
+        let state = PilotState::parse(&as_reply(&request)).unwrap();
+        let again = built(&state.to_pilot(), &caps).unwrap();
+        prop_assert_eq!(again, request);
+    }
+
+    #[test]
+    fn pilot_state_round_trips_through_serde(pilot in pilots()) {
+        let request = built(&pilot, &Capabilities::all()).unwrap();
+        let state = PilotState::parse(&as_reply(&request)).unwrap();
+
+        let encoded = serde_json::to_string(&state).unwrap();
+        let decoded: PilotState = serde_json::from_str(&encoded).unwrap();
+        prop_assert_eq!(decoded, state);
+    }
+
+    #[test]
+    fn typed_pilot_round_trips_through_serde(pilot in pilots()) {
+        let encoded = serde_json::to_string(&pilot).unwrap();
+        let decoded: Pilot = serde_json::from_str(&encoded).unwrap();
+        let caps = Capabilities::all();
+        prop_assert_eq!(built(&decoded, &caps), built(&pilot, &caps));
+    }
+}
@@ -0,0 +1,32 @@
+use wizard_rs::daemon::{DaemonError, Msg, Request, PROTOCOL_VERSION};
+
+#[test]
+fn old_requests_are_told_the_version() {
+    // version 1 ran a bare action list, with sleeps in seconds, on an ip
+    let old = br#"{"version":1,"msg":{"Run":[[{"Sleep":1}],"192.168.1.20"]}}"#;
+    assert_eq!(
+        Request::decode(old).unwrap_err(),
+        DaemonError::Version {
+            daemon: PROTOCOL_VERSION,
+            client: 1,
+        }
+    );
+
+    let unversioned = br#""Ping""#;
+    assert!(matches!(
+        Request::decode(unversioned),
+        Err(DaemonError::Version { client: 0, .. })
+    ));
+}
+
+#[test]
+fn current_requests_decode() {
+    let data = serde_json::to_vec(&Request::new(Msg::CurrentStep(3))).unwrap();
+    assert!(matches!(Request::decode(&data), Ok(Msg::CurrentStep(3))));
+
+    let garbage = format!(r#"{{"version":{},"msg":"Dance"}}"#, PROTOCOL_VERSION);
+    assert!(matches!(
+        Request::decode(garbage.as_bytes()),
+        Err(DaemonError::Malformed(_))
+    ));
+}
@@ -0,0 +1,373 @@
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use wizard_rs::capabilities::{BulbKind, Capabilities};
+use wizard_rs::pilot::{Method, Pilot, DEFAULT_RGB};
+use wizard_rs::program::{
+    interpolate, simulate, validate, Action, Colors, Easing, EndBehavior, Op, Program,
+    ProgramError, Severity,
+};
+use wizard_rs::scenes::Scene;
+
+fn pilot(brightness: f32, rgb: [u8; 3]) -> Pilot {
+    let mut pilot = Pilot::new(Method::SetPilot);
+    pilot.set_brightness(brightness);
+    pilot.set_rgb(rgb[0], rgb[1], rgb[2]);
+    pilot
+}
+
+#[test]
+fn fades_start_and_end_on_their_pilots() {
+    let from = pilot(0.2, [255, 0, 0]);
+    let to = pilot(1.0, [0, 0, 255]);
+    for easing in [Easing::Linear, Easing::EaseInOut, Easing::Perceptual] {
+        let start = interpolate(&from, &to, 0.0, easing);
+        assert!((start.brightness - 0.2).abs() < 1e-3);
+        assert_eq!(start.rgb, from.rgb);
+        assert_eq!(interpolate(&from, &to, 1.0, easing).rgb, to.rgb);
+    }
+
+    let half = interpolate(&from, &to, 0.5, Easing::Linear);
+    assert!((half.brightness - 0.6).abs() < 1e-3);
+    assert_eq!(
+        half.build().unwrap(),
+        pilot(0.6, [128, 0, 128]).build().unwrap()
+    );
+}
+
+#[test]
+fn perceptual_fades_linger_in_the_dark() {
+    let from = pilot(0.1, [255, 255, 255]);
+    let to = pilot(1.0, [255, 255, 255]);
+    let linear = interpolate(&from, &to, 0.5, Easing::Linear);
+    let perceptual = interpolate(&from, &to, 0.5, Easing::Perceptual);
+    assert!(perceptual.brightness < linear.brightness);
+}
+
+#[test]
+fn fading_out_stays_on_until_the_end() {
+    let from = pilot(1.0, [255, 255, 255]);
+    let mut off = from.clone();
+    off.set_state(false);
+
+    let frame = interpolate(&from, &off, 0.9, Easing::Linear);
+    assert!(frame.state);
+    frame.validate().unwrap();
+    assert!(!interpolate(&from, &off, 1.0, Easing::Linear).state);
+}
+
+#[test]
+fn sleeps_keep_milliseconds() {
+    let json = serde_json::to_string(&Action::Sleep(Duration::from_millis(250))).unwrap();
+    assert_eq!(json, r#"{"Sleep":{"ms":250}}"#);
+    match serde_json::from_str(&json).unwrap() {
+        Action::Sleep(duration) => assert_eq!(duration, Duration::from_millis(250)),
+        action => panic!("{:?}", action),
+    }
+}
+
+#[test]
+fn sleeps_saved_in_seconds_still_load() {
+    let program: Vec<Action> = serde_json::from_str(r#"[{"Sleep":2},{"Sleep":{"ms":5}}]"#).unwrap();
+    let durations: Vec<Duration> = program
+        .iter()
+        .filter_map(|action| match action {
+            Action::Sleep(duration) => Some(*duration),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        durations,
+        [Duration::from_secs(2), Duration::from_millis(5)]
+    );
+}
+
+fn sleep(ms: u64) -> Action {
+    Action::Sleep(Duration::from_millis(ms))
+}
+
+#[test]
+fn bare_action_lists_load_as_looping_programs() {
+    let program: Program = serde_json::from_str(r#"[{"Sleep":1}]"#).unwrap();
+    assert_eq!(program.actions.len(), 1);
+    assert_eq!(program.end, EndBehavior::Loop);
+
+    let program: Program =
+        serde_json::from_str(r#"{"actions":[{"Sleep":{"ms":5}}],"end":"Restore"}"#).unwrap();
+    assert_eq!(program.end, EndBehavior::Restore);
+}
+
+#[test]
+fn nested_repeats_compile_to_jumps() {
+    let program = Program::from(vec![
+        Action::Repeat {
+            count: 2,
+            body: vec![
+                sleep(1),
+                Action::Repeat {
+                    count: 3,
+                    body: vec![sleep(2)],
+                },
+            ],
+        },
+        Action::Stop,
+    ]);
+    let compiled = program.compile().unwrap();
+    assert_eq!(compiled.slots, 2);
+
+    let shape: Vec<String> = compiled
+        .ops
+        .iter()
+        .map(|op| match op {
+            Op::Do(_) => String::from("do"),
+            Op::Enter { slot, count, end } => format!("enter {} {} {}", slot, count, end),
+            Op::Again { slot, count, start } => format!("again {} {} {}", slot, count, start),
+            Op::Jump(target) => format!("jump {}", target),
+            Op::Hold => String::from("hold"),
+            Op::Stop => String::from("stop"),
+        })
+        .collect();
+    assert_eq!(
+        shape,
+        [
+            "enter 0 2 6",
+            "do",
+            "enter 1 3 5",
+            "do",
+            "again 1 3 3",
+            "again 0 2 1",
+            "stop",
+        ]
+    );
+}
+
+#[test]
+fn gotos_jump_to_labels_in_any_block() {
+    let program = Program::from(vec![
+        Action::Goto(String::from("end")),
+        sleep(1),
+        Action::Block(vec![Action::Label(String::from("end")), Action::Hold]),
+    ]);
+    let ops = program.compile().unwrap().ops;
+    assert!(matches!(ops[0], Op::Jump(2)));
+    assert!(matches!(ops[2], Op::Hold));
+
+    let unknown = Program::from(vec![Action::Goto(String::from("nowhere"))]);
+    assert_eq!(
+        unknown.compile().unwrap_err(),
+        ProgramError::UnknownLabel(String::from("nowhere"))
+    );
+    let twice = Program::from(vec![
+        Action::Label(String::from("a")),
+        Action::Label(String::from("a")),
+    ]);
+    assert_eq!(
+        twice.compile().unwrap_err(),
+        ProgramError::DuplicateLabel(String::from("a"))
+    );
+}
+
+/// The rgb each pick of `action` sets, from a generator seeded with `seed`.
+fn picks(action: &Action, seed: u64) -> Vec<Option<[f32; 3]>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..20)
+        .map(|_| match action.pick(&mut rng) {
+            Some(Action::SetPilot(pilot)) => pilot.rgb,
+            action => panic!("{:?}", action),
+        })
+        .collect()
+}
+
+#[test]
+fn seeded_picks_repeat() {
+    let action = Action::RandomColor {
+        from: Colors::Hues {
+            min: 0.0,
+            max: 360.0,
+        },
+        brightness: 1.0,
+    };
+    assert_eq!(picks(&action, 7), picks(&action, 7));
+    assert_ne!(picks(&action, 7), picks(&action, 8));
+}
+
+#[test]
+fn hue_ranges_wrap_past_red() {
+    // magentas through oranges: red is full and green never beats it
+    let action = Action::RandomColor {
+        from: Colors::Hues {
+            min: 300.0,
+            max: 30.0,
+        },
+        brightness: 1.0,
+    };
+    for rgb in picks(&action, 1) {
+        let [r, g, _] = rgb.unwrap();
+        assert_eq!(r, 1.0);
+        assert!(g <= 0.5 + 1e-3);
+    }
+}
+
+#[test]
+fn random_picks_stay_in_range() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let (min, max) = (Duration::from_millis(100), Duration::from_millis(200));
+    let sleep = Action::RandomSleep { min, max };
+    for _ in 0..50 {
+        match sleep.pick(&mut rng) {
+            Some(Action::Sleep(duration)) => assert!(min <= duration && duration <= max),
+            action => panic!("{:?}", action),
+        }
+    }
+
+    let scenes = Action::RandomScene {
+        scenes: vec![Scene::Ocean, Scene::Forest],
+        brightness: 0.5,
+    };
+    match scenes.pick(&mut rng) {
+        Some(Action::SetPilot(pilot)) => {
+            assert!(matches!(pilot.scene, Some(Scene::Ocean | Scene::Forest)))
+        }
+        action => panic!("{:?}", action),
+    }
+    let empty = Action::RandomScene {
+        scenes: Vec::new(),
+        brightness: 0.5,
+    };
+    assert!(empty.pick(&mut rng).is_none());
+}
+
+fn errors(program: &Program, caps: &[Capabilities]) -> Vec<(Vec<usize>, Severity)> {
+    validate(program, caps)
+        .into_iter()
+        .map(|issue| (issue.at, issue.severity))
+        .collect()
+}
+
+#[test]
+fn loops_that_never_wait_are_refused() {
+    let red = Action::SetPilot(pilot(1.0, [255, 0, 0]));
+    let all = [Capabilities::all()];
+
+    let flood = Program::from(vec![red.clone()]);
+    assert_eq!(errors(&flood, &all), [(vec![], Severity::Error)]);
+
+    let mut once = flood.clone();
+    once.end = EndBehavior::Stop;
+    assert!(errors(&once, &all).is_empty());
+
+    let waits = Program::from(vec![red.clone(), sleep(100)]);
+    assert!(errors(&waits, &all).is_empty());
+
+    // the sleep is jumped over
+    let goto = Program::from(vec![
+        Action::Label(String::from("top")),
+        red.clone(),
+        Action::Goto(String::from("top")),
+        sleep(100),
+    ]);
+    assert_eq!(errors(&goto, &all), [(vec![], Severity::Error)]);
+
+    // repeats end, but still flood the bulbs while they run
+    let repeat = Program::from(vec![
+        Action::Repeat {
+            count: 5,
+            body: vec![red],
+        },
+        sleep(100),
+    ]);
+    assert_eq!(errors(&repeat, &all), [(vec![0], Severity::Warning)]);
+
+    // nothing to do is not the same as looping forever
+    let empty = Program::from(vec![
+        Action::Repeat {
+            count: 5,
+            body: vec![Action::RandomScene {
+                scenes: Vec::new(),
+                brightness: 1.0,
+            }],
+        },
+        sleep(100),
+    ]);
+    assert!(!errors(&empty, &all).contains(&(vec![], Severity::Error)));
+}
+
+#[test]
+fn editor_steps_are_valid() {
+    // "set pilot", then the "rgb" button
+    let step = Pilot {
+        rgb: Some(DEFAULT_RGB),
+        ..Pilot::default()
+    };
+    let program = Program::from(vec![Action::SetPilot(step), sleep(100)]);
+    assert!(validate(&program, &[Capabilities::all()]).is_empty());
+}
+
+#[test]
+fn bad_settings_are_found_where_they_are() {
+    let mut both = pilot(1.0, [255, 0, 0]);
+    both.set_scene(Scene::Ocean);
+    let mut dim = pilot(1.0, [255, 0, 0]);
+    dim.set_brightness(0.01);
+    let program = Program::from(vec![
+        sleep(100),
+        Action::Block(vec![Action::SetPilot(both), Action::SetPilot(dim)]),
+    ]);
+    assert_eq!(
+        errors(&program, &[Capabilities::all()]),
+        [(vec![1, 0], Severity::Error), (vec![1, 1], Severity::Error)]
+    );
+
+    let color = Program::from(vec![Action::SetPilot(pilot(1.0, [255, 0, 0])), sleep(100)]);
+    let white = Capabilities::from_kind(BulbKind::DimmableWhite);
+    assert!(errors(&color, &[Capabilities::all()]).is_empty());
+    assert_eq!(
+        errors(&color, &[Capabilities::all(), white]),
+        [(vec![0], Severity::Error)]
+    );
+}
+
+const SECOND: Duration = Duration::from_secs(1);
+
+#[test]
+fn simulations_follow_the_clock() {
+    let mut program = Program::from(vec![
+        Action::SetPilot(pilot(1.0, [255, 0, 0])),
+        sleep(1000),
+        Action::Fade {
+            to: pilot(1.0, [0, 0, 255]),
+            duration_ms: 1000,
+            easing: Easing::Linear,
+        },
+    ]);
+    program.end = EndBehavior::Stop;
+    let timeline = simulate(&program, SECOND * 60, Duration::from_millis(100)).unwrap();
+    assert!(!timeline.cut);
+    assert_eq!(timeline.duration, SECOND * 2);
+    // the set, ten frames, then the end of the fade
+    assert_eq!(timeline.points.len(), 12);
+
+    let rgb = |secs: f32| timeline.at(Duration::from_secs_f32(secs)).unwrap().rgb;
+    assert_eq!(rgb(0.5), Some([1.0, 0.0, 0.0]));
+    assert_eq!(rgb(1.5), Some([0.5, 0.0, 0.5]));
+    assert_eq!(rgb(5.0), Some([0.0, 0.0, 1.0]));
+}
+
+#[test]
+fn simulations_of_endless_programs_stop_at_the_limit() {
+    let looping = Program::from(vec![Action::SetPilot(pilot(1.0, [255, 0, 0])), sleep(300)]);
+    let timeline = simulate(&looping, SECOND, SECOND).unwrap();
+    assert!(timeline.cut);
+    assert_eq!(timeline.duration, SECOND);
+    assert_eq!(timeline.points.len(), 4);
+
+    // a loop that never waits is cut off rather than hanging
+    let spinning = Program::from(vec![Action::SetPilot(pilot(1.0, [255, 0, 0]))]);
+    assert!(simulate(&spinning, SECOND, SECOND).unwrap().cut);
+
+    let held = Program::from(vec![sleep(100), Action::Hold]);
+    let timeline = simulate(&held, SECOND, SECOND).unwrap();
+    assert!(timeline.cut);
+    assert!(timeline.at(Duration::ZERO).is_none());
+}
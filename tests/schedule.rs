@@ -0,0 +1,145 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+use wizard_rs::pilot::{Method, Pilot};
+use wizard_rs::schedule::{Schedule, Task, Trigger, Window, WEEKDAYS};
+use wizard_rs::solar::{sun_event, Location, SunEvent};
+
+const LONDON: Location = Location {
+    latitude: 51.5,
+    longitude: -0.13,
+};
+
+fn at(hour: u32, minute: u32) -> Trigger {
+    Trigger::At(NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+}
+
+fn time(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    // 2024-01-01 was a Monday
+    NaiveDate::from_ymd_opt(2024, 1, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap()
+}
+
+fn schedule(at: Trigger) -> Schedule {
+    Schedule {
+        name: String::from("test"),
+        days: Vec::new(),
+        at,
+        bulbs: Vec::new(),
+        task: Task::Pilot(Pilot::new(Method::SetPilot)),
+        window: None,
+    }
+}
+
+#[test]
+fn next_start_is_later_the_same_day_or_the_next() {
+    let schedule = schedule(at(23, 30));
+    assert_eq!(
+        schedule.next_start(time(1, 12, 0), None),
+        Some(time(1, 23, 30))
+    );
+    assert_eq!(
+        schedule.next_start(time(1, 23, 30), None),
+        Some(time(2, 23, 30))
+    );
+}
+
+#[test]
+fn weekday_schedules_skip_the_weekend() {
+    let mut schedule = schedule(at(7, 0));
+    schedule.days = WEEKDAYS.to_vec();
+    // Friday morning, after the wake-up
+    assert_eq!(
+        schedule.next_start(time(5, 8, 0), None),
+        Some(time(8, 7, 0))
+    );
+    assert_eq!(
+        schedule.previous_start(time(7, 8, 0), None),
+        Some(time(5, 7, 0))
+    );
+}
+
+#[test]
+fn windows_close_after_their_start() {
+    let mut schedule = schedule(at(22, 0));
+    schedule.window = Some(Window {
+        until: at(6, 30),
+        then: Task::Pilot(Pilot::new(Method::SetPilot)),
+    });
+    assert_eq!(
+        schedule.window_end(time(1, 22, 0), None),
+        Some(time(2, 6, 30))
+    );
+
+    schedule.at = at(6, 0);
+    assert_eq!(
+        schedule.window_end(time(1, 6, 0), None),
+        Some(time(1, 6, 30))
+    );
+}
+
+#[test]
+fn upcoming_lists_the_next_starts() {
+    let schedule = schedule(at(7, 0));
+    assert_eq!(
+        schedule.upcoming(time(1, 8, 0), None, 3),
+        vec![time(2, 7, 0), time(3, 7, 0), time(4, 7, 0)]
+    );
+}
+
+#[test]
+fn sun_triggers_need_a_location() {
+    let schedule = schedule(Trigger::Sun {
+        event: SunEvent::Sunset,
+        offset_mins: -30,
+    });
+    assert_eq!(schedule.next_start(time(1, 8, 0), None), None);
+    assert_eq!(schedule.upcoming(time(1, 8, 0), Some(&LONDON), 4).len(), 4);
+}
+
+/// Minutes past midnight UTC.
+fn minutes(date: NaiveDate, event: SunEvent, location: &Location) -> Option<i64> {
+    let time = sun_event(date, event, location)?.time();
+    Some(time.hour() as i64 * 60 + time.minute() as i64)
+}
+
+#[test]
+fn sun_events_match_published_times() {
+    let midsummer = NaiveDate::from_ymd_opt(2024, 6, 20).unwrap();
+    let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+
+    // published: 03:43, 20:21 and 21:06 UTC; then 08:04 and 15:54 UTC
+    let close = |minutes: Option<i64>, expected: i64| (minutes.unwrap() - expected).abs() <= 3;
+    assert!(close(
+        minutes(midsummer, SunEvent::Sunrise, &LONDON),
+        3 * 60 + 43
+    ));
+    assert!(close(
+        minutes(midsummer, SunEvent::Sunset, &LONDON),
+        20 * 60 + 21
+    ));
+    assert!(close(
+        minutes(midsummer, SunEvent::CivilDusk, &LONDON),
+        21 * 60 + 6
+    ));
+    assert!(close(
+        minutes(midwinter, SunEvent::Sunrise, &LONDON),
+        8 * 60 + 4
+    ));
+    assert!(close(
+        minutes(midwinter, SunEvent::Sunset, &LONDON),
+        15 * 60 + 54
+    ));
+}
+
+#[test]
+fn the_sun_does_not_set_in_polar_summer() {
+    let tromso = Location {
+        latitude: 69.65,
+        longitude: 18.96,
+    };
+    let midsummer = NaiveDate::from_ymd_opt(2024, 6, 20).unwrap();
+    assert_eq!(sun_event(midsummer, SunEvent::Sunset, &tromso), None);
+    assert_eq!(sun_event(midsummer, SunEvent::Sunrise, &tromso), None);
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use wizard_rs::program::{Action, Colors, Easing, EndBehavior, Program};
+use wizard_rs::scenes::Scene;
+use wizard_rs::script::{parse, print, ScriptError};
+
+const WAKE_UP: &str = "
+// wake up slowly, then party
+end restore
+seed 7
+
+off
+fade 30s perceptual to color #ff8800 dim 40
+repeat 3 {
+    color #ff0000 dim 80; wait 500ms
+    scene Ocean speed 150
+    wait 100ms to 2s
+}
+random hues 300 30 dim 50
+random scene ocean Forest
+label party; goto party
+";
+
+#[test]
+fn programs_parse_from_text() {
+    let program = parse(WAKE_UP).unwrap();
+    assert_eq!(program.end, EndBehavior::Restore);
+    assert_eq!(program.seed, Some(7));
+    assert_eq!(program.actions.len(), 7);
+
+    match &program.actions[1] {
+        Action::Fade {
+            to,
+            duration_ms,
+            easing,
+        } => {
+            assert_eq!(*duration_ms, 30_000);
+            assert_eq!(*easing, Easing::Perceptual);
+            assert_eq!(to.dimming(), 40);
+            assert_eq!(to.rgb, Some([1.0, 136.0 / 255.0, 0.0]));
+        }
+        action => panic!("{:?}", action),
+    }
+    match &program.actions[2] {
+        Action::Repeat { count: 3, body } => {
+            assert!(matches!(body[1], Action::Sleep(d) if d == Duration::from_millis(500)));
+            assert!(matches!(&body[2], Action::SetPilot(p) if p.scene == Some(Scene::Ocean)));
+        }
+        action => panic!("{:?}", action),
+    }
+    assert!(matches!(
+        program.actions[3],
+        Action::RandomColor {
+            from: Colors::Hues { min, max },
+            ..
+        } if min == 300.0 && max == 30.0
+    ));
+}
+
+#[test]
+fn printed_programs_read_back_the_same() {
+    let program = parse(WAKE_UP).unwrap();
+    let text = print(&program);
+    let again = parse(&text).unwrap();
+    assert_eq!(print(&again), text);
+
+    let json = |program: &Program| serde_json::to_value(program).unwrap();
+    assert_eq!(json(&again), json(&program));
+}
+
+fn error(text: &str) -> (usize, usize) {
+    let ScriptError { line, column, .. } = parse(text).unwrap_err();
+    (line, column)
+}
+
+#[test]
+fn errors_point_at_the_mistake() {
+    assert_eq!(error("wait 1s\n  color #ff00zz"), (2, 9));
+    assert_eq!(error("wait 1s; jump"), (1, 10));
+    assert_eq!(error("repeat 2 {\n wait 1s\n"), (2, 9));
+    assert_eq!(error("}"), (1, 1));
+    assert_eq!(error("fade 1s to"), (1, 11));
+    assert_eq!(error("repeat 2 { seed 1 }"), (1, 12));
+
+    let e = parse("wait 1s\nscene Disco").unwrap_err();
+    assert_eq!(e.to_string(), "2:7: unknown scene `Disco`");
+}
@@ -0,0 +1,127 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use wizard_rs::bulb::Bulb;
+use wizard_rs::discovery::DiscoveryTarget;
+use wizard_rs::pilot::{Method, Pilot};
+use wizard_rs::sim::{SimBulb, SimConfig, Simulator};
+use wizard_rs::wizard::Wizard;
+
+// every test uses its own addresses, since they run in parallel
+
+fn known(sim: &SimBulb) -> Bulb {
+    let mut bulb = Bulb::new(sim.ip.to_string(), sim.mac.clone(), sim.mac.clone());
+    bulb.module_name = Some(sim.module_name.clone());
+    bulb
+}
+
+fn red() -> Pilot {
+    let mut pilot = Pilot::new(Method::SetPilot);
+    pilot.set_state(true);
+    pilot.set_brightness(0.5);
+    pilot.rgb = Some([1.0, 0.0, 0.0]);
+    pilot
+}
+
+#[test]
+fn set_and_get_pilot() {
+    let sim =
+        Simulator::start_range(Ipv4Addr::new(127, 0, 0, 20), 1, SimConfig::default()).unwrap();
+    let wiz = Wizard::bind(0).unwrap();
+    let mut bulb = known(&sim.bulbs()[0]);
+
+    wiz.set_pilot_ack(&mut bulb, &red()).unwrap();
+
+    let state = wiz.get_pilot(&bulb).unwrap();
+    assert_eq!(state.rgb(), Some([255, 0, 0]));
+    assert_eq!(state.dimming, Some(50));
+    assert_eq!(state.temp, None);
+    assert_eq!(sim.state(&bulb.mac).unwrap().r, Some(255));
+}
+
+#[test]
+fn discovery_collects_metadata() {
+    let sim =
+        Simulator::start_range(Ipv4Addr::new(127, 0, 0, 30), 3, SimConfig::default()).unwrap();
+    let mut wiz = Wizard::bind(0).unwrap();
+    wiz.discovery.targets = sim
+        .bulbs()
+        .iter()
+        .map(|bulb| DiscoveryTarget::Address(bulb.ip))
+        .collect();
+
+    let updates = wiz.discover().unwrap();
+    for _ in updates.iter() {}
+
+    let found = wiz.bulbs.lock().unwrap().clone();
+    assert_eq!(found.len(), 3);
+    for bulb in sim.bulbs() {
+        let found = found.iter().find(|b| b.mac == bulb.mac).unwrap();
+        assert_eq!(found.ip, bulb.ip.to_string());
+        assert_eq!(found.module_name.as_deref(), Some("ESP03_SHRGB1C_01"));
+        assert_eq!(found.fw_version.as_deref(), Some("1.25.0"));
+        assert_eq!(found.rssi, Some(-62));
+    }
+}
+
+#[test]
+fn set_pilot_ack_retries_lost_packets() {
+    let config = SimConfig {
+        loss: 0.2,
+        latency: Duration::from_millis(20),
+        seed: Some(7),
+    };
+    let sim = Simulator::start_range(Ipv4Addr::new(127, 0, 0, 40), 1, config).unwrap();
+    let mut wiz = Wizard::bind(0).unwrap();
+    wiz.discovery.targets = vec![DiscoveryTarget::Address(sim.bulbs()[0].ip)];
+    let mut bulb = known(&sim.bulbs()[0]);
+
+    for _ in 0..3 {
+        wiz.set_pilot_ack(&mut bulb, &red()).unwrap();
+    }
+    assert_eq!(sim.state(&bulb.mac).unwrap().r, Some(255));
+}
+
+#[test]
+fn unsupported_params_are_rejected() {
+    let mut white = SimBulb::new(Ipv4Addr::new(127, 0, 0, 50));
+    white.module_name = String::from("ESP01_SHTW1C_31");
+    let sim = Simulator::start(vec![white], SimConfig::default()).unwrap();
+    let wiz = Wizard::bind(0).unwrap();
+
+    // without a module name the client lets rgb through to the bulb
+    let mut bulb = known(&sim.bulbs()[0]);
+    bulb.module_name = None;
+
+    let result = wiz.set_pilot_ack(&mut bulb, &red());
+    assert!(matches!(
+        result,
+        Err(wizard_rs::error::WizardError::Rejected(_))
+    ));
+    assert_eq!(sim.state(&bulb.mac).unwrap().temp, Some(2700));
+}
+
+#[test]
+fn loss_outside_zero_to_one_is_refused() {
+    let config = SimConfig {
+        loss: 1.5,
+        ..SimConfig::default()
+    };
+    assert!(Simulator::start_range(Ipv4Addr::new(127, 0, 0, 60), 1, config).is_err());
+}
+
+#[test]
+fn fetch_pilot_finds_a_moved_bulb() {
+    let sim =
+        Simulator::start_range(Ipv4Addr::new(127, 0, 0, 70), 1, SimConfig::default()).unwrap();
+    let mut wiz = Wizard::bind(0).unwrap();
+    wiz.discovery.targets = vec![DiscoveryTarget::Address(sim.bulbs()[0].ip)];
+    let mut bulb = known(&sim.bulbs()[0]);
+    wiz.set_pilot_ack(&mut bulb, &red()).unwrap();
+
+    // nothing answers on the saved address any more
+    bulb.ip = String::from("127.0.0.71");
+    let (found, state) = wiz.fetch_pilot(bulb).recv().unwrap();
+    assert_eq!(found.ip, "127.0.0.70");
+    assert_eq!(state.unwrap().rgb(), Some([255, 0, 0]));
+}